@@ -14,8 +14,14 @@ use std::io::Write;
 use std::path::PathBuf;
 
 fn main() {
-    #[cfg(not(any(feature = "stm32f407ze", feature = "gd32f307ve")))]
+    #[cfg(not(any(feature = "stm32f407ze", feature = "gd32f307ve", feature = "simulator")))]
     compile_error!("No printer selected. Use make PRINTER=mono4k or cargo --features=mono4k");
+
+    // The simulator runs on the host, there's no linker script to provide.
+    if cfg!(feature = "simulator") {
+        return;
+    }
+
     #[allow(unused_variables)]
     let mcu = "unset";
 
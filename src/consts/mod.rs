@@ -9,3 +9,8 @@ pub use saturn::*;
 mod mono4k;
 #[cfg(feature="mono4k")]
 pub use mono4k::*;
+
+#[cfg(feature="simulator")]
+mod simulator;
+#[cfg(feature="simulator")]
+pub use simulator::*;
@@ -41,13 +41,22 @@ pub mod zaxis {
         pub const DRIVER_MICROSTEPS: u32 = 256;
         pub const FULL_STEPS_PER_REVOLUTION: u32 = 200;
         pub const SCREW_THREAD_PITCH_MM: f32 = 2.0;
-        pub const MOTOR_CURRENT_PERCENT: u32 = 70;
+        // Percentage of the driver full scale current, set with VREF.
+        pub const MOTOR_RUN_CURRENT_PERCENT: u32 = 70;
+        pub const MOTOR_HOLD_CURRENT_PERCENT: u32 = 30;
     }
 
     pub mod motion_control {
         pub const MAX_SPEED: f32 = 20.0; // mm/s
         pub const MAX_ACCELERATION: f32 = 25.0; // mm/s^2
         pub const MAX_DECELERATION: f32 = 60.0; // mm/s^2
+        // Used by the S-curve profile, for moves that need to be gentle.
+        pub const MAX_JERK: f32 = 500.0; // mm/s^3
+        // Soft limits of the Z-axis, enforced once homed.
+        pub const MIN_POSITION_MM: f32 = 0.0;
+        pub const MAX_POSITION_MM: f32 = 165.0;
+        // How far the plate moves, at most, when jogging before homing.
+        pub const JOG_DISTANCE_MM: f32 = 40.0;
     }
 
     pub mod stepper {
@@ -79,7 +88,72 @@ pub mod zaxis {
         // the bottom sensor activates. We are going at slow speed, but we are
         // going through a small distance.
         pub const PHASE3_HOMING_SPEED_MM_PER_SEC: f32 = 0.2;
+
+        // Each phase moves a bounded distance. If the sensor doesn't change
+        // state within it, the sensor is broken or disconnected.
+        // Phase 1 covers the whole Z-axis, and a bit more.
+        pub const PHASE1_MAX_DISTANCE_MM: f32 = 175.0;
+        pub const PHASE2_MAX_DISTANCE_MM: f32 = 10.0;
+        pub const PHASE3_MAX_DISTANCE_MM: f32 = 1.0;
+    }
+
+    pub mod peel_control {
+        // Used with a load cell on the build plate, see zaxis::peel_control.
+        // Above this force, the lift slows down right away, and the next
+        // layers start slower.
+        pub const FORCE_LIMIT_G: f32 = 1500.0;
+        // The layer is free when the force falls back under this.
+        pub const RELEASE_FORCE_G: f32 = 100.0;
+        // Range of the slow lift speed.
+        pub const MIN_SPEED_MM_PER_SEC: f32 = 0.5;
+        pub const MAX_SPEED_MM_PER_SEC: f32 = 5.0;
+        pub const SPEED_DECREASE_FACTOR: f32 = 0.7;
+        pub const SPEED_INCREASE_FACTOR: f32 = 1.1;
+        // The next layers go faster when the layer comes free within this
+        // fraction of the slow lift distance.
+        pub const EARLY_RELEASE_RATIO: f32 = 0.5;
+        // HX711 samples at 10Hz at worst.
+        pub const SAMPLE_TIMEOUT_MS: u64 = 500;
     }
+
+    pub mod lift_policy {
+        // Lift between layers depending on the cured area of the layer, see
+        // zaxis::lift_policy. Columns are: cured area (mm^2), lift height
+        // (mm), peel speed (mm/s), wait before exposure (s).
+        pub const DEFAULT_CURVE: [(f32, f32, f32, f32); 3] = [
+            (    0.0, 5.0, 3.0, 0.5),
+            ( 2000.0, 6.0, 1.5, 1.0),
+            (10000.0, 8.0, 0.5, 2.0),
+        ];
+    }
+}
+
+pub mod load_cell {
+    // HX711 at a gain of 128 with a 10kg load cell (1mV/V). Calibrate with a
+    // known weight for your own cell.
+    pub const GRAMS_PER_COUNT: f32 = 0.005;
+    // Averaged at boot to find the zero.
+    pub const TARE_NUM_SAMPLES: u32 = 10;
+}
+
+pub mod uv_light {
+    // The exposure timer ticks at 10kHz, a tenth of a millisecond.
+    pub const EXPOSURE_TIMER_FREQ: u32 = 10_000;
+    pub const PWM_FREQ_KHZ: u32 = 10;
+    // Full power, when the print file doesn't say.
+    pub const DEFAULT_PWM: u8 = 255;
+}
+
+pub mod print {
+    // Going down to the first layer, and up out of the vat at the end.
+    pub const DESCEND_SPEED_MM_PER_SEC: f32 = 5.0;
+    pub const FINISH_SPEED_MM_PER_SEC: f32 = 5.0;
+    // Default height of the plate when a print is paused, see settings.
+    pub const PAUSE_HEIGHT_MM: f32 = 100.0;
+    // Power-loss checkpoints are a log of records over a few flash sectors.
+    // A sector is erased once every time the log goes through it.
+    pub const CHECKPOINT_SECTOR_SIZE: u32 = 4096;
+    pub const CHECKPOINT_NUM_SECTORS: u32 = 4;
 }
 
 pub mod io {
@@ -0,0 +1,152 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The simulator mimics the Elegoo Saturn: same display, LCD, and Z-axis.
+
+#![allow(dead_code)]
+
+pub mod system {
+    // Only used for cycle accounting. There's no real clock to configure.
+    pub const CLOCK_SPEED_MHZ: u32 = 168;
+}
+
+pub mod display {
+    pub const WIDTH: u16 = 480;
+    pub const HEIGHT: u16 = 320;
+    pub const LVCONF_PATH: &str = "480x320"; // Used by the Makefile
+    // Normally 1/10th of the display size
+    pub const LVGL_BUFFER_LEN: usize = 7680;
+}
+
+pub mod lcd {
+    pub const WIDTH: u32 = 3840;
+    pub const HEIGHT: u32 = 2400;
+}
+
+pub mod zaxis {
+    pub mod hardware {
+        pub const DRIVER_MICROSTEPS: u32 = 256;
+        pub const FULL_STEPS_PER_REVOLUTION: u32 = 200;
+        pub const SCREW_THREAD_PITCH_MM: f32 = 2.0;
+        pub const MOTOR_RUN_CURRENT_PERCENT: u32 = 70;
+        pub const MOTOR_HOLD_CURRENT_PERCENT: u32 = 30;
+    }
+
+    pub mod motion_control {
+        pub const MAX_SPEED: f32 = 20.0; // mm/s
+        pub const MAX_ACCELERATION: f32 = 25.0; // mm/s^2
+        pub const MAX_DECELERATION: f32 = 60.0; // mm/s^2
+        // Used by the S-curve profile, for moves that need to be gentle.
+        pub const MAX_JERK: f32 = 500.0; // mm/s^3
+        // Soft limits of the Z-axis, enforced once homed.
+        pub const MIN_POSITION_MM: f32 = 0.0;
+        pub const MAX_POSITION_MM: f32 = 195.0;
+        // How far the plate moves, at most, when jogging before homing.
+        pub const JOG_DISTANCE_MM: f32 = 40.0;
+    }
+
+    pub mod stepper {
+        // Same timer resolution as the real hardware, so the step generator
+        // behaves identically.
+        pub const STEP_TIMER_FREQ: u32 = 1_000_000;
+        pub const STEP_TIMER_MIN_DELAY_VALUE: f32 = 15.0;
+    }
+
+    pub mod origin_calibration {
+        pub const BOTTOM_SENSOR_POSITION_MM: f32 = 2.0;
+        pub const PHASE1_HOMING_SPEED_MM_PER_SEC: f32 = 10.0;
+        pub const PHASE2_HOMING_SPEED_MM_PER_SEC: f32 = 2.0;
+        pub const PHASE3_HOMING_SPEED_MM_PER_SEC: f32 = 0.2;
+
+        // Each phase moves a bounded distance. If the sensor doesn't change
+        // state within it, the sensor is broken or disconnected.
+        // Phase 1 covers the whole Z-axis, and a bit more.
+        pub const PHASE1_MAX_DISTANCE_MM: f32 = 210.0;
+        pub const PHASE2_MAX_DISTANCE_MM: f32 = 10.0;
+        pub const PHASE3_MAX_DISTANCE_MM: f32 = 1.0;
+    }
+
+    pub mod peel_control {
+        // Used with a load cell on the build plate, see zaxis::peel_control.
+        // Above this force, the lift slows down right away, and the next
+        // layers start slower.
+        pub const FORCE_LIMIT_G: f32 = 1500.0;
+        // The layer is free when the force falls back under this.
+        pub const RELEASE_FORCE_G: f32 = 100.0;
+        // Range of the slow lift speed.
+        pub const MIN_SPEED_MM_PER_SEC: f32 = 0.5;
+        pub const MAX_SPEED_MM_PER_SEC: f32 = 5.0;
+        pub const SPEED_DECREASE_FACTOR: f32 = 0.7;
+        pub const SPEED_INCREASE_FACTOR: f32 = 1.1;
+        // The next layers go faster when the layer comes free within this
+        // fraction of the slow lift distance.
+        pub const EARLY_RELEASE_RATIO: f32 = 0.5;
+        // HX711 samples at 10Hz at worst.
+        pub const SAMPLE_TIMEOUT_MS: u64 = 500;
+    }
+
+    pub mod lift_policy {
+        // Lift between layers depending on the cured area of the layer, see
+        // zaxis::lift_policy. Columns are: cured area (mm^2), lift height
+        // (mm), peel speed (mm/s), wait before exposure (s).
+        pub const DEFAULT_CURVE: [(f32, f32, f32, f32); 3] = [
+            (    0.0, 5.0, 3.0, 0.5),
+            ( 2000.0, 6.0, 1.5, 1.0),
+            (10000.0, 8.0, 0.5, 2.0),
+        ];
+    }
+}
+
+pub mod load_cell {
+    // Same as a HX711 at a gain of 128 with a 10kg load cell.
+    pub const GRAMS_PER_COUNT: f32 = 0.005;
+    pub const TARE_NUM_SAMPLES: u32 = 10;
+    // The mock load cell samples at 80Hz, like a HX711 with RATE high.
+    pub const SAMPLE_PERIOD_MS: u64 = 12;
+    // See load_cell::PeelModel. At the maximum peel speed, the force goes
+    // over the limit of peel_control.
+    pub const PEEL_RELEASE_DISTANCE_MM: f32 = 1.0;
+    pub const PEEL_STIFFNESS_G_PER_MM: f32 = 500.0;
+    pub const PEEL_DAMPING_G_PER_MM_PER_SEC: f32 = 400.0;
+}
+
+pub mod uv_light {
+    // The exposure timer ticks at 10kHz, a tenth of a millisecond.
+    pub const EXPOSURE_TIMER_FREQ: u32 = 10_000;
+    // Full power, when the print file doesn't say.
+    pub const DEFAULT_PWM: u8 = 255;
+}
+
+pub mod print {
+    // Going down to the first layer, and up out of the vat at the end.
+    pub const DESCEND_SPEED_MM_PER_SEC: f32 = 5.0;
+    pub const FINISH_SPEED_MM_PER_SEC: f32 = 5.0;
+    // Default height of the plate when a print is paused, see settings.
+    pub const PAUSE_HEIGHT_MM: f32 = 100.0;
+    // Power-loss checkpoints are a log of records over a few flash sectors.
+    // A sector is erased once every time the log goes through it.
+    pub const CHECKPOINT_SECTOR_SIZE: u32 = 4096;
+    pub const CHECKPOINT_NUM_SECTORS: u32 = 4;
+}
+
+pub mod io {
+    // This should be at least one block_size = 512 to avoid degrading perfs
+    pub const FILE_READER_BUFFER_SIZE: usize = 1024;
+}
+
+pub mod touch_screen {
+    // Delay between two touch screen polls when the script has nothing to say.
+    pub const SLEEP_DELAY_MS: u64 = 20;
+}
+
+pub mod simulator {
+    // Where the plate is when the simulator boots. Somewhere in the middle of
+    // the Z-axis, so that homing has something to do.
+    pub const INITIAL_PLATE_POSITION_MM: f32 = 50.0;
+    // The physical position of the bottom sensor. When the plate is below
+    // this, the sensor is active.
+    pub const BOTTOM_SENSOR_TRIGGER_MM: f32 = 1.7;
+    pub const Z_AXIS_LENGTH_MM: f32 = 200.0;
+    // Default location of the files produced by the simulator: the stepper
+    // position trace, and the LCD captures.
+    pub const DEFAULT_OUTPUT_DIR: &str = "sim_output";
+}
@@ -1,14 +1,18 @@
 use embassy_util::Forever;
+#[cfg(not(feature="simulator"))]
 use cortex_m::peripheral::DWT;
 
 use crate::consts::system::*;
 
+#[cfg(not(feature="simulator"))]
 static CYCLE_COUNTER: Forever<CycleCounter> = Forever::new();
 
+#[cfg(not(feature="simulator"))]
 pub struct CycleCounter {
    dwt: DWT,
 }
 
+#[cfg(not(feature="simulator"))]
 impl CycleCounter {
     pub fn new(mut dwt: DWT) -> Self {
         //DWT::unlock();
@@ -25,11 +29,20 @@ impl CycleCounter {
     }
 }
 
+#[cfg(not(feature="simulator"))]
 #[inline(always)]
 pub fn read_cycles() -> u32 {
     unsafe { CYCLE_COUNTER.steal().cycles() }
 }
 
+// On the host, we derive the cycle count from the wall clock, so that timing
+// measurements are still meaningful.
+#[cfg(feature="simulator")]
+pub fn read_cycles() -> u32 {
+    let elapsed = embassy_time::Instant::now().as_micros();
+    (elapsed as u32).wrapping_mul(CLOCK_SPEED_MHZ)
+}
+
 pub fn count_cycles<R>(mut f: impl FnMut() -> R) -> R {
     let start_cycles = read_cycles();
     let ret = f();
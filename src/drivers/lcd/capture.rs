@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use alloc::vec::Vec;
+
+use super::{Color8, MaskLcd};
+
+/// An in-memory LCD. It keeps the last complete frame, with colors reduced to
+/// the bit depth of the panel it stands in for.
+pub struct CaptureLcd {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    frame: Vec<Color8>,
+    drawing: bool,
+    num_frames: u32,
+}
+
+impl CaptureLcd {
+    pub fn new(width: u32, height: u32, bit_depth: u8) -> Self {
+        assert!((1..=8).contains(&bit_depth));
+        Self { width, height, bit_depth, frame: Vec::new(), drawing: false, num_frames: 0 }
+    }
+
+    /// The last frame. Pixels are stored row by row.
+    pub fn frame(&self) -> &[Color8] {
+        &self.frame
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Color8 {
+        self.frame[(y * self.width + x) as usize]
+    }
+
+    /// Number of frames that were completed.
+    pub fn num_frames(&self) -> u32 {
+        self.num_frames
+    }
+
+    /// Number of pixels that are not black, the area that gets cured.
+    pub fn lit_pixel_count(&self) -> u32 {
+        self.frame.iter().filter(|c| **c != 0).count() as u32
+    }
+}
+
+impl MaskLcd for CaptureLcd {
+    fn resolution(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    fn begin_frame(&mut self) {
+        self.frame.clear();
+        self.frame.reserve((self.width * self.height) as usize);
+        self.drawing = true;
+    }
+
+    fn push_run(&mut self, color: Color8, repeat: u32) {
+        debug_assert!(self.drawing, "push_run() called outside of a frame");
+        // Drop the bits the panel can't display
+        let mask = !(0xFF_u8.checked_shr(self.bit_depth as u32).unwrap_or(0));
+        let len = self.frame.len() + repeat as usize;
+        self.frame.resize(len, color & mask);
+    }
+
+    fn end_frame(&mut self) {
+        let expected = (self.width * self.height) as usize;
+        if self.frame.len() != expected {
+            warn!("LCD frame has {} pixels, expected {}", self.frame.len(), expected);
+        }
+        // Like the real panels, we ignore what overflows, and leave the rest black.
+        self.frame.resize(expected, 0);
+        self.drawing = false;
+        self.num_frames += 1;
+    }
+}
@@ -0,0 +1,31 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use super::Color8;
+
+/// A mask LCD panel. Frames are sent as runs of pixels, row by row, from the
+/// top left corner. This is how the print files store the layer images, and
+/// it's also what the panels like to receive.
+pub trait MaskLcd {
+    /// (width, height) in pixels.
+    fn resolution(&self) -> (u32, u32);
+
+    /// Number of grayscale bits per pixel that the panel can display.
+    /// Colors are given as Color8, and the lower bits get dropped.
+    fn bit_depth(&self) -> u8;
+
+    fn begin_frame(&mut self);
+
+    /// Pushes `repeat` pixels of the given color.
+    fn push_run(&mut self, color: Color8, repeat: u32);
+
+    /// The frame must have received exactly width*height pixels.
+    fn end_frame(&mut self);
+
+    /// Turns all the pixels black, so no light goes through.
+    fn blank(&mut self) {
+        let (width, height) = self.resolution();
+        self.begin_frame();
+        self.push_run(0, width * height);
+        self.end_frame();
+    }
+}
@@ -10,5 +10,16 @@ mod mono4k;
 #[cfg(feature="mono4k")]
 pub use mono4k::*;
 
+#[cfg(feature="simulator")]
+mod simulator;
+#[cfg(feature="simulator")]
+pub use simulator::*;
+
 mod canvas;
 pub use canvas::*;
+
+mod mask_lcd;
+pub use mask_lcd::*;
+
+mod capture;
+pub use capture::*;
@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::drivers::lcd::Color8;
+use crate::drivers::lcd::{Color8, MaskLcd};
+use crate::consts::lcd::*;
 use crate::consts::io::*;
 
 // Color is 4 bpp grayscale
@@ -10,41 +11,50 @@ pub const BLACK: u8 = 0x00;
 
 use super::Lcd;
 
-pub struct Framebuffer<'a> {
-    lcd: &'a mut Lcd,
+/// Pixels are sent 4 by 4. This holds the pixels that are not sent yet.
+#[derive(Default)]
+pub struct Framebuffer {
     pending_pixels: u16,
     pending_pixels_cnt: u8, // modulo 4
 }
 
-impl<'a> Framebuffer<'a> {
-    pub fn new(lcd: &'a mut Lcd) -> Self {
-        lcd.start_drawing_raw();
-        Self { lcd, pending_pixels: 0, pending_pixels_cnt: 0 }
+impl MaskLcd for Lcd {
+    fn resolution(&self) -> (u32, u32) {
+        (WIDTH, HEIGHT)
     }
 
-    pub fn push_pixels(&mut self, color: Color8, mut repeat: u32) {
+    fn bit_depth(&self) -> u8 {
+        4
+    }
+
+    fn begin_frame(&mut self) {
+        self.fb = Default::default();
+        self.start_drawing_raw();
+    }
+
+    fn push_run(&mut self, color: Color8, mut repeat: u32) {
         let color = (color >> 4) as u16;
 
         if repeat == 0 { return }
 
         // First, flush any packed pending pixels.
         // Writing the code like this makes it fast. Performance is critical here.
-        if self.pending_pixels_cnt == 1 {
+        if self.fb.pending_pixels_cnt == 1 {
             repeat -= 1;
-            self.pending_pixels = (self.pending_pixels << 4) | color;
-            self.pending_pixels_cnt += 1;
+            self.fb.pending_pixels = (self.fb.pending_pixels << 4) | color;
+            self.fb.pending_pixels_cnt += 1;
             if repeat == 0 { return }
         }
-        if self.pending_pixels_cnt == 2 {
+        if self.fb.pending_pixels_cnt == 2 {
             repeat -= 1;
-            self.pending_pixels = (self.pending_pixels << 4) | color;
-            self.pending_pixels_cnt += 1;
+            self.fb.pending_pixels = (self.fb.pending_pixels << 4) | color;
+            self.fb.pending_pixels_cnt += 1;
             if repeat == 0 { return }
         }
-        if self.pending_pixels_cnt == 3 {
+        if self.fb.pending_pixels_cnt == 3 {
             repeat -= 1;
-            self.lcd.send_data((self.pending_pixels << 4) | color);
-            self.pending_pixels_cnt = 0;
+            self.send_data((self.fb.pending_pixels << 4) | color);
+            self.fb.pending_pixels_cnt = 0;
             if repeat == 0 { return }
         }
 
@@ -53,21 +63,19 @@ impl<'a> Framebuffer<'a> {
 
         // Now we flush pixels 4 by 4
         for _ in 0..repeat/4 {
-            self.lcd.send_data(packed_pixels);
+            self.send_data(packed_pixels);
         }
 
         // We may have some leftovers, save them for later
-        self.pending_pixels = packed_pixels;
-        self.pending_pixels_cnt = (repeat % 4) as u8;
+        self.fb.pending_pixels = packed_pixels;
+        self.fb.pending_pixels_cnt = (repeat % 4) as u8;
     }
-}
 
-impl<'a> Drop for Framebuffer<'a> {
-    fn drop(&mut self) {
+    fn end_frame(&mut self) {
         // If there's pending pixels, oh well.
-        if self.pending_pixels_cnt > 0 {
+        if self.fb.pending_pixels_cnt > 0 {
             debug!("WARN: leftover pixels")
         }
-        self.lcd.stop_drawing_raw();
+        self.stop_drawing_raw();
     }
 }
@@ -17,6 +17,7 @@ use super::Framebuffer;
 pub struct Lcd {
     cs: Output<'static, p::PA4>,
     spi: Spi<'static, p::SPI1, p::DMA1_CH3, p::DMA1_CH2>,
+    pub(super) fb: Framebuffer,
 }
 
 impl Lcd {
@@ -38,7 +39,7 @@ impl Lcd {
         let cfg = Config::default();
         let spi = Spi::new(spi1, sck, mosi, miso, dma_tx, dma_rx, p::SPI1::frequency(), cfg);
 
-        Self { cs, spi }
+        Self { cs, spi, fb: Default::default() }
     }
 
     pub const COLS: u16 = 3840;
@@ -53,8 +54,8 @@ impl Lcd {
         // Nothing to do.
     }
 
-    pub fn draw(&mut self) -> Canvas {
-        Canvas::new(Framebuffer::new(self))
+    pub fn draw(&mut self) -> Canvas<Self> {
+        Canvas::new(self)
     }
 
     pub fn start_drawing_raw(&mut self) {
@@ -1,24 +1,21 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::drivers::lcd::Color8;
+use crate::consts::lcd::*;
+use crate::drivers::lcd::{Color8, MaskLcd};
 use super::Lcd;
 
 /// This framebuffer uses 7-bit grascale values
 pub type Color7 = u8;
 
-pub struct Framebuffer<'a> {
-    lcd: &'a mut Lcd,
+/// State of the pixel encoder while a frame is being sent.
+#[derive(Default)]
+pub struct Framebuffer {
     color: Color7,
     color_repeat: u32,
     total_pixel_count: u32,
 }
 
-impl<'a> Framebuffer<'a> {
-    pub fn new(lcd: &'a mut Lcd) -> Self {
-        lcd.start_drawing_raw();
-        Self { lcd, color: 0, color_repeat: 0, total_pixel_count: 0 }
-    }
-
+impl Lcd {
     fn flush_pixels(&mut self) {
         // Data flows bytes per byte. The meaning of a byte is the following:
         // - if its 0x80 bit is set, then it means, draw a pixel of shade
@@ -41,51 +38,64 @@ impl<'a> Framebuffer<'a> {
         // (fortunately, 3d printing images is).
         const REPEAT_WINDOW_SIZE: u32 = 1920 as u32;
 
-        let encoded_color = ((self.color as u16 * 0x7C)/0x7F) as u8 | 0x80;
+        let encoded_color = ((self.fb.color as u16 * 0x7C)/0x7F) as u8 | 0x80;
 
-        while self.color_repeat > 0 {
-            self.lcd.send_data(encoded_color);
+        while self.fb.color_repeat > 0 {
+            self.send_data(encoded_color);
 
-            self.total_pixel_count += 1;
-            self.color_repeat -= 1;
+            self.fb.total_pixel_count += 1;
+            self.fb.color_repeat -= 1;
 
-            let window_position = self.total_pixel_count % REPEAT_WINDOW_SIZE;
+            let window_position = self.fb.total_pixel_count % REPEAT_WINDOW_SIZE;
             if window_position > 0 {
-                let mut repeat = self.color_repeat.min(REPEAT_WINDOW_SIZE - window_position);
+                let mut repeat = self.fb.color_repeat.min(REPEAT_WINDOW_SIZE - window_position);
 
-                self.color_repeat -= repeat;
-                self.total_pixel_count += repeat;
+                self.fb.color_repeat -= repeat;
+                self.fb.total_pixel_count += repeat;
 
                 while repeat > 0 {
                     // The value 0x7E is also forbidden as it seems to indicate
                     // commands as well. 0x7F seems to work, but the original
                     // firmware doesn't use it.
                     let n = repeat.min(0x7d);
-                    self.lcd.send_data(n as u8);
+                    self.send_data(n as u8);
                     repeat -= n;
                 }
             }
         }
     }
+}
+
+impl MaskLcd for Lcd {
+    fn resolution(&self) -> (u32, u32) {
+        (WIDTH, HEIGHT)
+    }
 
-    pub fn push_pixels(&mut self, color: Color8, repeat: u32) {
+    fn bit_depth(&self) -> u8 {
+        7
+    }
+
+    fn begin_frame(&mut self) {
+        self.fb = Default::default();
+        self.start_drawing_raw();
+    }
+
+    fn push_run(&mut self, color: Color8, repeat: u32) {
         let color: Color7 = color >> 1;
 
-        if color == self.color {
-            self.color_repeat += repeat as u32;
+        if color == self.fb.color {
+            self.fb.color_repeat += repeat as u32;
             return;
         }
 
         self.flush_pixels();
 
-        self.color = color;
-        self.color_repeat = repeat as u32;
+        self.fb.color = color;
+        self.fb.color_repeat = repeat as u32;
     }
-}
 
-impl<'a> Drop for Framebuffer<'a> {
-    fn drop(&mut self) {
+    fn end_frame(&mut self) {
         self.flush_pixels();
-        self.lcd.stop_drawing_raw();
+        self.stop_drawing_raw();
     }
 }
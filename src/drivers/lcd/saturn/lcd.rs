@@ -26,6 +26,7 @@ enum Command {
 pub struct Lcd {
     cs: Output<'static, p::PA15>,
     spi: Spi<p::PC7, p::PG3, p::PC6, SPI_FREQ_HZ>,
+    pub(super) fb: Framebuffer,
 }
 
 impl Lcd {
@@ -40,7 +41,7 @@ impl Lcd {
         let mosi = Output::new(mosi, Level::Low, Speed::Medium);
         let miso = Input::new(miso, Pull::None);
         let spi = Spi::new(clk, mosi, miso);
-        Self { cs, spi }
+        Self { cs, spi, fb: Default::default() }
     }
 
     pub fn init(&mut self) {
@@ -55,8 +56,8 @@ impl Lcd {
         }
     }
 
-    pub fn draw(&mut self) -> Canvas {
-        Canvas::new(Framebuffer::new(self))
+    pub fn draw(&mut self) -> Canvas<Self> {
+        Canvas::new(self)
     }
 
     pub fn start_drawing_raw(&mut self) {
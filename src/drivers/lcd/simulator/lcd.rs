@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The virtual LCD captures every frame that is drawn into a PGM image file
+// (8bpp grayscale), named frame_00000.pgm, frame_00001.pgm, and so on.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::consts::lcd::*;
+use crate::drivers::lcd::{Canvas, CaptureLcd, Color8, MaskLcd};
+
+pub struct Lcd {
+    capture: CaptureLcd,
+    output_dir: Option<PathBuf>,
+}
+
+impl Lcd {
+    pub fn new(output_dir: Option<PathBuf>) -> Self {
+        // Same bit depth as the Saturn's panel.
+        let capture = CaptureLcd::new(WIDTH, HEIGHT, 7);
+        Self { capture, output_dir }
+    }
+
+    pub fn init(&mut self) {
+        debug!("LCD resolution is {}x{}", WIDTH, HEIGHT);
+    }
+
+    pub fn draw(&mut self) -> Canvas<Self> {
+        Canvas::new(self)
+    }
+
+    pub fn capture(&self) -> &CaptureLcd {
+        &self.capture
+    }
+
+    fn write_pgm(path: &Path, frame: &[Color8]) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "P5\n{} {}\n255\n", WIDTH, HEIGHT)?;
+        file.write_all(frame)?;
+        file.flush()
+    }
+}
+
+impl MaskLcd for Lcd {
+    fn resolution(&self) -> (u32, u32) {
+        self.capture.resolution()
+    }
+
+    fn bit_depth(&self) -> u8 {
+        self.capture.bit_depth()
+    }
+
+    fn begin_frame(&mut self) {
+        self.capture.begin_frame();
+    }
+
+    #[inline]
+    fn push_run(&mut self, color: Color8, repeat: u32) {
+        self.capture.push_run(color, repeat);
+    }
+
+    fn end_frame(&mut self) {
+        self.capture.end_frame();
+
+        if let Some(dir) = self.output_dir.as_ref() {
+            let frame_index = self.capture.num_frames() - 1;
+            let path = dir.join(format!("frame_{:05}.pgm", frame_index));
+            if let Err(e) = Self::write_pgm(&path, self.capture.frame()) {
+                warn!("Failed to write {}: {}", path.display(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::future::Future;
+
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::drivers::delay_ns;
+
+use super::ForceSensor;
+
+/// Input channel and gain of the next conversion.
+#[derive(Clone, Copy, Debug)]
+pub enum Hx711Gain {
+    ChannelA128,
+    ChannelB32,
+    ChannelA64,
+}
+
+impl Hx711Gain {
+    // Clock pulses after the 24 data bits that select the next conversion.
+    fn extra_pulses(self) -> u32 {
+        match self {
+            Hx711Gain::ChannelA128 => 1,
+            Hx711Gain::ChannelB32 => 2,
+            Hx711Gain::ChannelA64 => 3,
+        }
+    }
+}
+
+/// The HX711 24-bit ADC, read with two GPIOs. It samples at 10Hz or 80Hz
+/// depending on how its RATE pin is strapped.
+pub struct Hx711<DOUT: InputPin, SCK: OutputPin> {
+    dout: DOUT,
+    sck: SCK,
+    gain: Hx711Gain,
+}
+
+impl<DOUT: InputPin, SCK: OutputPin> Hx711<DOUT, SCK> {
+    // How often we check if a conversion is ready.
+    const POLL_INTERVAL: Duration = Duration::from_millis(1);
+    // Minimum duration for the high and low levels of SCK.
+    const SCK_HALF_PERIOD_NS: u32 = 200;
+
+    pub fn new(dout: DOUT, mut sck: SCK, gain: Hx711Gain) -> Self {
+        // Holding SCK high for more than 60us powers down the chip.
+        let _ = sck.set_low();
+        Self { dout, sck, gain }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.dout.is_low().unwrap_or(false)
+    }
+
+    fn pulse(&mut self) -> bool {
+        let _ = self.sck.set_high();
+        delay_ns(Self::SCK_HALF_PERIOD_NS);
+        let bit = self.dout.is_high().unwrap_or(false);
+        let _ = self.sck.set_low();
+        delay_ns(Self::SCK_HALF_PERIOD_NS);
+        bit
+    }
+
+    // Takes about 12us. If we get preempted for more than 60us with SCK
+    // high, the chip powers down and the sample is lost. The step timer
+    // interrupt is much shorter than that.
+    fn shift_in(&mut self) -> i32 {
+        let mut value: u32 = 0;
+        for _ in 0..24 {
+            value = (value << 1) | self.pulse() as u32;
+        }
+        for _ in 0..self.gain.extra_pulses() {
+            self.pulse();
+        }
+        // Sign extends the 24-bit two's complement value
+        ((value << 8) as i32) >> 8
+    }
+}
+
+impl<DOUT: InputPin, SCK: OutputPin> ForceSensor for Hx711<DOUT, SCK> {
+    type ReadFuture<'a> = impl Future<Output = i32> + 'a where Self: 'a;
+
+    fn read_raw(&mut self) -> Self::ReadFuture<'_> {
+        async move {
+            while !self.is_ready() {
+                Timer::after(Self::POLL_INTERVAL).await;
+            }
+            self.shift_in()
+        }
+    }
+}
@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::future::Future;
+
+use embassy_time::{Duration, Timer};
+
+use crate::drivers::zaxis::prelude::*;
+
+use super::ForceSensor;
+
+/// A load cell that samples force_fn() at a fixed rate. With a PeelModel in
+/// force_fn(), this is good enough to exercise the peel control loop in the
+/// simulator.
+pub struct MockForceSensor<F: FnMut() -> f32> {
+    force_fn: F,
+    sample_period: Duration,
+    counts_per_gram: f32,
+}
+
+impl<F: FnMut() -> f32> MockForceSensor<F> {
+    /// force_fn() returns grams. Raw samples are counts_per_gram times that.
+    pub fn new(force_fn: F, sample_period: Duration, counts_per_gram: f32) -> Self {
+        Self { force_fn, sample_period, counts_per_gram }
+    }
+}
+
+impl<F: FnMut() -> f32> ForceSensor for MockForceSensor<F> {
+    type ReadFuture<'a> = impl Future<Output = i32> + 'a where Self: 'a;
+
+    fn read_raw(&mut self) -> Self::ReadFuture<'_> {
+        async move {
+            Timer::after(self.sample_period).await;
+            ((self.force_fn)() * self.counts_per_gram) as i32
+        }
+    }
+}
+
+/// A rough model of the force needed to peel a layer. Once the plate starts
+/// lifting, the FEP stretches like a spring, and the suction resists
+/// proportionally to the speed. The layer comes free after release_distance.
+/// The next layer starts where the plate stops going down.
+#[derive(Clone, Copy, Debug)]
+pub struct PeelModel {
+    pub start: Steps,
+    pub release_distance: Steps,
+    // g/mm
+    pub stiffness: f32,
+    // g/(mm/s)
+    pub damping: f32,
+    // Seconds between two calls of force(), to estimate the speed.
+    pub sample_period: f32,
+    last_position: Steps,
+}
+
+impl PeelModel {
+    pub fn new(start: Steps, release_distance: Steps, stiffness: f32, damping: f32, sample_period: f32) -> Self {
+        Self { start, release_distance, stiffness, damping, sample_period, last_position: start }
+    }
+
+    pub fn force(&mut self, position: Steps) -> f32 {
+        let speed = (position - self.last_position).as_mm() / self.sample_period;
+        if position < self.last_position {
+            self.start = position;
+        }
+        self.last_position = position;
+
+        let stretch = position - self.start;
+        if stretch.0 <= 0 || stretch >= self.release_distance {
+            0.0
+        } else {
+            self.stiffness * stretch.as_mm() + self.damping * speed.max(0.0)
+        }
+    }
+}
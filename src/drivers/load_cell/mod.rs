@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Measures the force on the build plate, to detect how hard a layer peels off
+// the FEP. The printers don't come with a load cell, it's an add-on mounted
+// between the Z-axis arm and the build plate, usually read with an HX711.
+
+use core::future::Future;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use embassy_util::channel::signal::Signal;
+
+use crate::consts::load_cell::*;
+
+#[cfg(all(feature="load_cell", feature="mono4k"))]
+compile_error!("The load cell pins of the Mono 4K are unknown");
+
+#[cfg(not(feature="simulator"))]
+mod hx711;
+#[cfg(not(feature="simulator"))]
+pub use hx711::*;
+
+mod mock;
+pub use mock::*;
+
+// The HX711 of the add-on, see Machine. misc/saturn_ports.txt shows PF2-PF7
+// as pull-down inputs that the stock firmware doesn't use. Nothing says where
+// they go on the board, check before wiring.
+#[cfg(all(feature="load_cell", feature="saturn"))]
+pub type BoardLoadCell = Hx711<
+    embassy_stm32::gpio::Input<'static, embassy_stm32::peripherals::PF2>,
+    embassy_stm32::gpio::Output<'static, embassy_stm32::peripherals::PF3>,
+>;
+
+pub trait ForceSensor {
+    type ReadFuture<'a>: Future<Output = i32> + 'a where Self: 'a;
+    /// Waits for the next sample, and returns it as the raw ADC value.
+    fn read_raw(&mut self) -> Self::ReadFuture<'_>;
+}
+
+/// Converts raw samples into grams. Positive values pull on the plate.
+#[derive(Clone, Copy, Debug)]
+pub struct Calibration {
+    pub offset: i32,
+    pub grams_per_count: f32,
+}
+
+impl Calibration {
+    pub fn to_grams(&self, raw: i32) -> f32 {
+        (raw - self.offset) as f32 * self.grams_per_count
+    }
+
+    /// Sets the offset so that the current load reads as 0g. The plate must
+    /// not be touching anything.
+    pub async fn tare(&mut self, sensor: &mut impl ForceSensor, num_samples: u32) {
+        assert!(num_samples > 0);
+        let mut sum: i64 = 0;
+        for _ in 0..num_samples {
+            sum += sensor.read_raw().await as i64;
+        }
+        self.offset = (sum / num_samples as i64) as i32;
+    }
+}
+
+/// Where the sampling task publishes the force measurements.
+pub struct ForceMonitor {
+    // f32 bits
+    latest: AtomicU32,
+    present: AtomicBool,
+    signal: Signal<f32>,
+}
+
+pub static FORCE_MONITOR: ForceMonitor = ForceMonitor::new();
+
+impl ForceMonitor {
+    pub const fn new() -> Self {
+        Self { latest: AtomicU32::new(0), present: AtomicBool::new(false), signal: Signal::new() }
+    }
+
+    pub fn publish(&self, grams: f32) {
+        self.latest.store(grams.to_bits(), Ordering::Relaxed);
+        self.present.store(true, Ordering::Relaxed);
+        self.signal.signal(grams);
+    }
+
+    /// True once the sampling task publishes samples.
+    pub fn is_present(&self) -> bool {
+        self.present.load(Ordering::Relaxed)
+    }
+
+    /// The most recent sample, in grams.
+    pub fn latest(&self) -> f32 {
+        f32::from_bits(self.latest.load(Ordering::Relaxed))
+    }
+
+    /// Waits for the next sample, in grams. There's a single waiter at a time.
+    pub async fn next_sample(&self) -> f32 {
+        self.signal.reset();
+        self.signal.wait().await
+    }
+}
+
+/// Body of the sampling task. The sensor paces the loop.
+pub async fn sample_forever(sensor: &mut impl ForceSensor, calibration: &Calibration, monitor: &ForceMonitor) -> ! {
+    loop {
+        let raw = sensor.read_raw().await;
+        monitor.publish(calibration.to_grams(raw));
+    }
+}
+
+/// Body of the load cell task. Tares first, the plate must be free at boot.
+pub async fn run(sensor: &mut impl ForceSensor, monitor: &ForceMonitor) -> ! {
+    let mut calibration = Calibration { offset: 0, grams_per_count: GRAMS_PER_COUNT };
+    calibration.tare(sensor, TARE_NUM_SAMPLES).await;
+    sample_forever(sensor, &calibration, monitor).await
+}
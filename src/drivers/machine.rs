@@ -5,9 +5,11 @@ use crate::drivers::{
     touch_screen::TouchScreen,
     zaxis,
     lcd::Lcd,
+    uv_light,
     CycleCounter,
     touch_screen::*,
     usb::UsbHost, delay_ms,
+    load_cell,
 };
 
 #[cfg(feature="saturn")]
@@ -22,14 +24,18 @@ pub struct Machine {
     pub display: Display,
     pub touch_screen: TouchScreen,
     pub lcd: Lcd,
+    pub uv: uv_light::UvExposure,
     pub usb_host: UsbHost,
-    #[cfg(feature="mono4k")]
     pub stepper: zaxis::MotionControl,
-    #[cfg(feature="mono4k")]
     pub z_bottom_sensor: zaxis::BottomSensor,
+    #[cfg(feature="load_cell")]
+    pub load_cell: load_cell::BoardLoadCell,
+    pub settings: Settings,
 }
 
-use embassy_stm32::{Peripherals, gpio::Input, Config, time::Hertz};
+use crate::settings::Settings;
+
+use embassy_stm32::{Peripherals, gpio::{Input, Output, Level, Pull, Speed}, Config, time::Hertz};
 
 impl Machine {
     pub fn new(cp: cortex_m::Peripherals, p: Peripherals) -> Self {
@@ -48,6 +54,11 @@ impl Machine {
             p.PG15, p.PB3, p.PB4, p.PB5, p.SPI3, p.DMA1_CH2, p.DMA1_CH5
         ).expect("Failed to initialize the external spi flash");
 
+        #[cfg(feature="saturn")]
+        let settings = Settings::load(&mut ext_flash);
+        #[cfg(not(feature="saturn"))]
+        let settings = Settings::default();
+
         /*
             This is how the saturn is configured. Not sure what all these pins do.
             use embassy_stm32::gpio::{Level, Input, Output, Speed, Pull};
@@ -147,12 +158,11 @@ impl Machine {
         // UV Light
         //--------------------------
         #[cfg(feature="mono4k")]
-        {
-            // This turns on the UV light:
-            // use embassy_stm32::gpio::{Level, Input, Output, Speed, Pull};
-            // core::mem::forget(Output::new(p.PA1, Level::High, Speed::Low)); // PWM
-            // core::mem::forget(Output::new(p.PB7, Level::High, Speed::Low)); // Master switch
-        }
+        let uv_light = uv_light::UvLed::new(p.PA1, p.PB7, p.TIM5);
+        #[cfg(feature="saturn")]
+        let uv_light = uv_light::UnknownUvLight::new();
+
+        let uv = uv_light::UvExposure::new(uv_light, p.TIM6);
 
         //--------------------------
         // USB Host
@@ -171,20 +181,34 @@ impl Machine {
             );
         }
 
+        #[cfg(feature="saturn")]
+        let z_bottom_sensor = zaxis::BottomSensor::new(p.PC13);
         #[cfg(feature="mono4k")]
         let z_bottom_sensor = zaxis::BottomSensor::new(
             p.PB3,
             // pb4 is normally the top sensor
         );
 
+        #[cfg(feature="saturn")]
+        let driver = zaxis::StepDirDriver::new(p.PF15, p.PA6, p.PD12);
         #[cfg(feature="mono4k")]
-        let drv8424 = zaxis::Drv8424::new(
+        let driver = zaxis::Drv8424::new(
             p.PE4, p.PE5, p.PE6, p.PC3, p.PC0, p.PC1, p.PC2,
             p.PA3, p.TIM2,
         );
 
-        #[cfg(feature="mono4k")]
-        let stepper = zaxis::MotionControl::new(drv8424, p.TIM7);
+        let mut stepper = zaxis::MotionControl::new(driver, p.TIM7);
+        stepper.set_backlash(settings.zaxis_backlash());
+
+        //--------------------------
+        //  Load cell (add-on)
+        //--------------------------
+        #[cfg(feature="load_cell")]
+        let load_cell = load_cell::Hx711::new(
+            Input::new(p.PF2, Pull::None),
+            Output::new(p.PF3, Level::Low, Speed::Low),
+            load_cell::Hx711Gain::ChannelA128,
+        );
 
         Self {
             #[cfg(feature="saturn")]
@@ -192,11 +216,13 @@ impl Machine {
             display,
             touch_screen,
             lcd,
+            uv,
             usb_host,
-            #[cfg(feature="mono4k")]
             stepper,
-            #[cfg(feature="mono4k")]
-            z_bottom_sensor
+            z_bottom_sensor,
+            #[cfg(feature="load_cell")]
+            load_cell,
+            settings,
          }
     }
 }
@@ -1,20 +1,31 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+#[cfg(not(feature="simulator"))]
 pub mod machine;
 #[cfg(feature="saturn")]
 pub mod ext_flash;
 #[cfg(feature = "gd32f307ve")]
 pub mod gd32f307_clock;
+#[cfg(not(feature="simulator"))]
 pub mod display;
 pub mod touch_screen;
 pub mod zaxis;
+pub mod load_cell;
+pub mod uv_light;
 pub mod lcd;
+#[cfg(not(feature="simulator"))]
 pub mod usb;
 mod delay;
 pub use delay::*;
 
+// The simulator replaces the hardware with virtual devices.
+#[cfg(feature="simulator")]
+pub mod simulator;
+#[cfg(feature="simulator")]
+pub use simulator::{machine, display, usb};
 
 mod cycle_counter;
 pub use cycle_counter::*;
 
+#[cfg(not(feature="simulator"))]
 pub mod clock;
@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The virtual TFT display keeps the pixels in memory. A screenshot can be
+// saved as a PPM image file.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use alloc::{vec, vec::Vec};
+use embedded_graphics::{
+    prelude::*,
+    pixelcolor::Rgb565,
+};
+
+use crate::consts::display::*;
+
+pub struct Display {
+    pixels: Vec<Rgb565>,
+    backlight: bool,
+}
+
+impl Display {
+    pub fn new() -> Self {
+        let pixels = vec![Rgb565::BLACK; WIDTH as usize * HEIGHT as usize];
+        Self { pixels, backlight: false }
+    }
+
+    pub fn init(&mut self) {}
+
+    pub fn set_backlight(&mut self, value: bool) {
+        self.backlight = value;
+    }
+
+    pub fn save_screenshot(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "P6\n{} {}\n255\n", WIDTH, HEIGHT)?;
+        for color in &self.pixels {
+            // Rgb565 to Rgb888
+            let rgb = [
+                (color.r() << 3) | (color.r() >> 2),
+                (color.g() << 2) | (color.g() >> 4),
+                (color.b() << 3) | (color.b() >> 2),
+            ];
+            file.write_all(&rgb)?;
+        }
+        file.flush()
+    }
+}
+
+impl DrawTarget for Display {
+    type Color = Rgb565;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(coord, color) in pixels.into_iter() {
+            const W: i32 = WIDTH as i32;
+            const H: i32 = HEIGHT as i32;
+            if (0..W).contains(&coord.x) && (0..H).contains(&coord.y) {
+                self.pixels[(coord.y*W + coord.x) as usize] = color;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl OriginDimensions for Display {
+    fn size(&self) -> Size {
+        Size::new(WIDTH.into(), HEIGHT.into())
+    }
+}
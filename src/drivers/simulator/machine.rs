@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::drivers::{
+    display::Display,
+    touch_screen::TouchScreen,
+    zaxis,
+    lcd::Lcd,
+    uv_light,
+    usb::UsbHost,
+};
+
+use crate::settings::{Settings, FileStorage};
+
+use super::Config;
+
+pub struct Machine {
+    pub display: Display,
+    pub touch_screen: TouchScreen,
+    pub lcd: Lcd,
+    pub uv: uv_light::UvExposure,
+    pub usb_host: UsbHost,
+    pub stepper: zaxis::MotionControl,
+    pub z_bottom_sensor: zaxis::BottomSensor,
+    pub settings: Settings,
+}
+
+impl Machine {
+    pub fn new(config: &Config) -> Self {
+        let mut display = Display::new();
+        display.init();
+        display.set_backlight(true);
+
+        let touch_screen = TouchScreen::new(config.touch_script.as_deref());
+
+        let lcd = Lcd::new(Some(config.output_dir.clone()));
+
+        let uv = uv_light::UvExposure::new(
+            uv_light::MockUvLight::new(),
+            uv_light::simulator::VirtualExposureTimer::new(),
+        );
+
+        let usb_host = UsbHost::new(config.usb_image.clone());
+
+        let z_bottom_sensor = zaxis::BottomSensor::new();
+
+        let mut storage = FileStorage(config.output_dir.join("settings.bin"));
+        let mut settings = Settings::load(&mut storage);
+        if !config.settings.is_empty() {
+            for assignment in &config.settings {
+                settings.set(assignment)
+                    .unwrap_or_else(|e| panic!("Invalid setting {}: {:?}", assignment, e));
+            }
+            settings.save(&mut storage);
+        }
+
+        let trace_path = config.output_dir.join("zaxis_trace.csv");
+        let mut stepper = zaxis::MotionControl::new(
+            zaxis::simulator::VirtualStepper::new(Some(&trace_path)),
+            zaxis::simulator::VirtualStepTimer::new(),
+        );
+        stepper.set_backlash(settings.zaxis_backlash());
+
+        Self {
+            display,
+            touch_screen,
+            lcd,
+            uv,
+            usb_host,
+            stepper,
+            z_bottom_sensor,
+            settings,
+        }
+    }
+}
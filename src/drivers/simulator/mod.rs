@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The simulator runs the firmware on the host with virtual hardware. The
+// board-specific drivers live next to the real ones (e.g. lcd/simulator), this
+// module holds the devices that have no board-specific counterpart, and the
+// simulator configuration.
+
+pub mod display;
+pub mod usb;
+pub mod machine;
+
+use std::path::PathBuf;
+
+use crate::consts::simulator::*;
+
+pub struct Config {
+    /// Disk image (FAT32) that is presented as the USB flash drive.
+    pub usb_image: Option<PathBuf>,
+    /// Touch input script, see touch_screen/simulator.rs for the format.
+    pub touch_script: Option<PathBuf>,
+    /// Where the stepper position trace and the LCD frames are written.
+    pub output_dir: PathBuf,
+    /// Check the step generator profiles instead of running the firmware.
+    pub validate_step_generator: bool,
+    /// File on the USB drive (8.3 name) to print at boot.
+    pub print_file: Option<String>,
+    /// Layers before this one are skipped.
+    pub print_start_layer: u32,
+    /// Changes to the persistent settings, e.g. "zaxis_backlash_um=20".
+    /// They are saved before the firmware starts.
+    pub settings: Vec<String>,
+}
+
+impl Config {
+    pub fn from_args() -> Self {
+        let mut config = Self {
+            usb_image: None,
+            touch_script: None,
+            output_dir: PathBuf::from(DEFAULT_OUTPUT_DIR),
+            validate_step_generator: false,
+            print_file: None,
+            print_start_layer: 0,
+            settings: Vec::new(),
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || PathBuf::from(args.next()
+                .unwrap_or_else(|| Self::usage(&format!("{} requires a value", arg))));
+
+            match arg.as_str() {
+                "--usb-image" => config.usb_image = Some(value()),
+                "--touch-script" => config.touch_script = Some(value()),
+                "--output-dir" => config.output_dir = value(),
+                "--validate-step-generator" => config.validate_step_generator = true,
+                "--print" => config.print_file = Some(value().to_string_lossy().into_owned()),
+                "--start-layer" => config.print_start_layer = value().to_string_lossy().parse()
+                    .unwrap_or_else(|_| Self::usage("--start-layer requires a layer number")),
+                "--set" => config.settings.push(value().to_string_lossy().into_owned()),
+                _ => Self::usage(&format!("Unknown argument: {}", arg)),
+            }
+        }
+
+        std::fs::create_dir_all(&config.output_dir)
+            .unwrap_or_else(|e| panic!("Failed to create {}: {}", config.output_dir.display(), e));
+
+        config
+    }
+
+    fn usage(error: &str) -> ! {
+        eprintln!("{}", error);
+        eprintln!("Usage: app [--usb-image FILE] [--touch-script FILE] [--output-dir DIR] [--validate-step-generator] [--print FILE [--start-layer N]] [--set KEY=VALUE]...");
+        std::process::exit(1);
+    }
+}
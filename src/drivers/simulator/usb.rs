@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The virtual USB host exposes a disk image file on the host as the USB flash
+// drive. The image must contain a FAT32 partition, like a real flash drive.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use core::future::Future;
+use embedded_sdmmc::{BlockDevice, Block, BlockIdx, BlockCount};
+
+use crate::util::io::{FileSystem, Result as FsResult};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum UsbError {
+    DeviceDisconnected,
+    TransactionError,
+    InvalidBlockSize,
+}
+
+pub struct UsbHost {
+    image_path: Option<PathBuf>,
+}
+
+impl UsbHost {
+    pub fn new(image_path: Option<PathBuf>) -> Self {
+        Self { image_path }
+    }
+
+    pub fn on_interrupt(&mut self) {}
+
+    pub async fn wait_for_filesystem(&mut self) -> FsResult<FileSystem> {
+        let path = match self.image_path.as_ref() {
+            Some(path) => path,
+            // No image given, the flash drive never gets plugged in.
+            None => futures::future::pending().await,
+        };
+
+        let block_device = MscBlockDevice::open(path)
+            .map_err(embedded_sdmmc::Error::DeviceError)?;
+
+        debug!("Disk initialized");
+        FileSystem::mount(block_device).await
+    }
+}
+
+pub struct MscBlockDevice {
+    block_count: u32,
+    file: Mutex<File>,
+}
+
+impl MscBlockDevice {
+    pub fn open(path: &PathBuf) -> Result<Self, UsbError> {
+        let file = File::options().read(true).write(true).open(path).map_err(|e| {
+            warn!("Failed to open {}: {}", path.display(), e);
+            UsbError::DeviceDisconnected
+        })?;
+
+        let size = file.metadata().map_err(|_| UsbError::TransactionError)?.len();
+        if size % Block::LEN as u64 != 0 {
+            debug!("Disk image size is not a multiple of the block size");
+            return Err(UsbError::InvalidBlockSize);
+        }
+        debug!("Disk size: {}MB", size/1024/1024);
+
+        let block_count = (size / Block::LEN as u64) as u32;
+        Ok(Self { block_count, file: Mutex::new(file) })
+    }
+
+    fn seek_to_block(file: &mut File, block_idx: BlockIdx) -> Result<(), UsbError> {
+        let offset = block_idx.0 as u64 * Block::LEN as u64;
+        file.seek(SeekFrom::Start(offset)).map_err(|_| UsbError::TransactionError)?;
+        Ok(())
+    }
+}
+
+impl BlockDevice for MscBlockDevice {
+    type Error = UsbError;
+
+    type ReadFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+    type WriteFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+
+    fn read<'a>(&'a self, blocks: &'a mut [Block], start_block_idx: BlockIdx, _reason: &str) -> Self::ReadFuture<'a> {
+        async move {
+            let mut file = self.file.lock().unwrap();
+            Self::seek_to_block(&mut file, start_block_idx)?;
+            for block in blocks {
+                file.read_exact(&mut block.contents).map_err(|_| UsbError::TransactionError)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn write<'a>(&'a self, blocks: &'a [Block], start_block_idx: BlockIdx) -> Self::WriteFuture<'a> {
+        async move {
+            let mut file = self.file.lock().unwrap();
+            Self::seek_to_block(&mut file, start_block_idx)?;
+            for block in blocks {
+                file.write_all(&block.contents).map_err(|_| UsbError::TransactionError)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Self::Error> {
+        Ok(BlockCount(self.block_count))
+    }
+}
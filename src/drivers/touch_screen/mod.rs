@@ -10,4 +10,9 @@ mod mono4k;
 #[cfg(feature="mono4k")]
 pub use mono4k::*;
 
+#[cfg(feature="simulator")]
+mod simulator;
+#[cfg(feature="simulator")]
+pub use simulator::*;
+
 // TODO Merge the two implementation in one.
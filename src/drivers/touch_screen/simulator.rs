@@ -0,0 +1,106 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Scripted touch input for the simulator. The script is a text file with one
+// command per line:
+//   wait <ms>        Do nothing for a while
+//   press <x> <y>    Touch the screen at (x,y)
+//   release          Stop touching the screen
+//   tap <x> <y>      Shorthand for press, wait 100, release
+// Empty lines and lines starting with # are ignored.
+
+use std::path::Path;
+
+use alloc::vec::Vec;
+use embassy_time::{Duration, Timer};
+
+const TAP_DURATION_MS: u64 = 100;
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct TouchEvent {
+    pub x: u16,
+    pub y: u16,
+    pub z: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Command {
+    Wait(u64),
+    Press(u16, u16),
+    Release,
+}
+
+pub struct TouchScreen {
+    script: Vec<Command>,
+    next_command: usize,
+}
+
+impl TouchScreen {
+    pub fn new(script_path: Option<&Path>) -> Self {
+        let script = script_path.map(|path| {
+            let content = std::fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+            Self::parse_script(&content)
+                .unwrap_or_else(|line| panic!("{}:{}: invalid touch command", path.display(), line))
+        }).unwrap_or_default();
+
+        Self { script, next_command: 0 }
+    }
+
+    // Returns the line number on error.
+    fn parse_script(content: &str) -> Result<Vec<Command>, usize> {
+        let mut script = Vec::new();
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let args: Vec<&str> = line.split_whitespace().collect();
+            let num = |n: usize| args.get(n).and_then(|v| v.parse().ok()).ok_or(i+1);
+
+            match args[0] {
+                "wait" => script.push(Command::Wait(num(1)? as u64)),
+                "press" => script.push(Command::Press(num(1)?, num(2)?)),
+                "release" => script.push(Command::Release),
+                "tap" => {
+                    script.push(Command::Press(num(1)?, num(2)?));
+                    script.push(Command::Wait(TAP_DURATION_MS));
+                    script.push(Command::Release);
+                }
+                _ => return Err(i+1),
+            }
+        }
+
+        Ok(script)
+    }
+
+    pub async fn get_next_touch_event(&mut self) -> Option<TouchEvent> {
+        loop {
+            let cmd = match self.script.get(self.next_command) {
+                Some(cmd) => *cmd,
+                None => {
+                    // End of script. Nobody will touch the screen anymore.
+                    futures::future::pending::<()>().await;
+                    unreachable!();
+                }
+            };
+            self.next_command += 1;
+
+            match cmd {
+                Command::Wait(ms) => Timer::after(Duration::from_millis(ms)).await,
+                Command::Press(x, y) => return Some(TouchEvent { x, y, z: 0 }),
+                Command::Release => return None,
+            }
+        }
+    }
+}
+
+pub fn into_lvgl_event(e: &Option<TouchEvent>) -> lvgl::core::TouchPad {
+    use lvgl::core::TouchPad;
+    if let Some(e) = e.as_ref() {
+        TouchPad::Pressed { x: e.x as i16, y: e.y as i16 }
+    } else {
+        TouchPad::Released
+    }
+}
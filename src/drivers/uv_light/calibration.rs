@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The irradiance of the UV LEDs doesn't scale linearly with the PWM duty, and
+// differs from one printer to the next. A calibration is a set of PWM duties
+// with the irradiance measured at the LCD surface with a UV meter. Between two
+// points, values are interpolated. It is stored in the settings.
+// Print files give the UV power as a PWM duty, assuming that the irradiance
+// follows it linearly. With a calibration, we pick the duty that actually
+// gives that fraction of the full power irradiance, see linearize().
+
+use super::UvPwm;
+
+pub const MAX_UV_CALIBRATION_POINTS: usize = 8;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UvCalibrationPoint {
+    pub pwm: u32, // 0 to 255
+    pub irradiance_mw_per_cm2: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UvCalibration {
+    // Sorted by pwm and by irradiance. Only the first `len` are used.
+    points: [UvCalibrationPoint; MAX_UV_CALIBRATION_POINTS],
+    len: u32,
+}
+
+impl UvCalibration {
+    // Fails if there are too many points, if a pwm is out of range, or if the
+    // points are not increasing.
+    pub fn new(points: &[UvCalibrationPoint]) -> Result<Self, ()> {
+        if points.len() > MAX_UV_CALIBRATION_POINTS ||
+            points.iter().any(|p| p.pwm > UvPwm::MAX as u32) ||
+            points.windows(2).any(|p|
+                p[0].pwm >= p[1].pwm || p[0].irradiance_mw_per_cm2 >= p[1].irradiance_mw_per_cm2
+            ) {
+            return Err(());
+        }
+
+        let mut this = Self::default();
+        this.points[..points.len()].copy_from_slice(points);
+        this.len = points.len() as u32;
+        Ok(this)
+    }
+
+    pub fn points(&self) -> &[UvCalibrationPoint] {
+        // len comes from the flash, it may be anything.
+        &self.points[..(self.len as usize).min(MAX_UV_CALIBRATION_POINTS)]
+    }
+
+    pub fn is_calibrated(&self) -> bool {
+        self.points().len() >= 2
+    }
+
+    // None when not calibrated.
+    pub fn irradiance(&self, pwm: UvPwm) -> Option<f32> {
+        self.interpolate(pwm as f32, |p| p.pwm as f32, |p| p.irradiance_mw_per_cm2)
+    }
+
+    // The PWM duty that gives `irradiance_mw_per_cm2`. None when not
+    // calibrated.
+    pub fn pwm(&self, irradiance_mw_per_cm2: f32) -> Option<UvPwm> {
+        self.interpolate(irradiance_mw_per_cm2, |p| p.irradiance_mw_per_cm2, |p| p.pwm as f32)
+            .map(|pwm| (pwm + 0.5).clamp(0.0, UvPwm::MAX as f32) as UvPwm)
+    }
+
+    // The PWM duty giving pwm/255 of the irradiance at full duty. Unchanged
+    // when not calibrated.
+    pub fn linearize(&self, pwm: UvPwm) -> UvPwm {
+        self.irradiance(UvPwm::MAX)
+            .and_then(|full| self.pwm(full * pwm as f32 / UvPwm::MAX as f32))
+            .unwrap_or(pwm)
+    }
+
+    // Clamped to the first and last points.
+    fn interpolate(
+        &self,
+        x: f32,
+        fx: impl Fn(&UvCalibrationPoint) -> f32,
+        fy: impl Fn(&UvCalibrationPoint) -> f32,
+    ) -> Option<f32> {
+        if !self.is_calibrated() {
+            return None;
+        }
+
+        let points = self.points();
+        let first = &points[0];
+        let last = &points[points.len()-1];
+
+        if x <= fx(first) {
+            return Some(fy(first));
+        }
+
+        for p in points.windows(2) {
+            let (a, b) = (&p[0], &p[1]);
+            if x <= fx(b) {
+                let t = (x - fx(a)) / (fx(b) - fx(a));
+                return Some(fy(a) + (fy(b) - fy(a)) * t);
+            }
+        }
+
+        Some(fy(last))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(pwm: u32, irradiance_mw_per_cm2: f32) -> UvCalibrationPoint {
+        UvCalibrationPoint { pwm, irradiance_mw_per_cm2 }
+    }
+
+    #[test]
+    fn linearize() {
+        assert_eq!(UvCalibration::default().linearize(100), 100);
+
+        // The LEDs saturate: half the duty gives 80% of the irradiance.
+        let calibration = UvCalibration::new(&[
+            point(0, 0.0), point(128, 4.0), point(255, 5.0),
+        ]).unwrap();
+        assert_eq!(calibration.linearize(255), 255);
+        assert_eq!(calibration.linearize(0), 0);
+        // 2mW/cm², half of the way to 128.
+        assert_eq!(calibration.linearize(102), 64);
+    }
+}
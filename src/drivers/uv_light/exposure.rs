@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use embassy_time::Duration;
+use embassy_util::channel::signal::Signal;
+
+use crate::consts::uv_light::*;
+use crate::util::SharedWithInterrupt;
+
+use super::{UvLight, UvPwm, ExposureTimer, BoardUvLight, BoardExposureTimer};
+
+fn duration_to_ticks(duration: Duration) -> u32 {
+    let ticks = duration.as_micros() * EXPOSURE_TIMER_FREQ as u64 / 1_000_000;
+    ticks.min(u32::MAX as u64) as u32
+}
+
+// Runs in the exposure timer interrupt. The timer counts on 16 bits, long
+// exposures are made of several timer periods.
+pub struct ExposureControl<L: UvLight, T: ExposureTimer> {
+    light: L,
+    timer: T,
+    remaining_ticks: u32,
+}
+
+impl<L: UvLight, T: ExposureTimer> ExposureControl<L, T> {
+    pub fn new(light: L, mut timer: T) -> Self {
+        timer.init();
+        let mut this = Self { light, timer, remaining_ticks: 0 };
+        this.turn_off();
+        this
+    }
+
+    fn start(&mut self, ticks: u32, pwm: UvPwm) {
+        self.remaining_ticks = ticks;
+        self.light.set_pwm(pwm);
+        self.light.set_enabled(true);
+        self.schedule_next_period();
+    }
+
+    fn schedule_next_period(&mut self) {
+        let ticks = self.remaining_ticks.min(u16::MAX as u32);
+        self.remaining_ticks -= ticks;
+        self.timer.start(ticks as u16);
+    }
+
+    fn turn_off(&mut self) {
+        self.light.set_enabled(false);
+        self.timer.stop();
+        self.remaining_ticks = 0;
+    }
+
+    // Returns true when the exposure is over.
+    pub fn on_interrupt(&mut self) -> bool {
+        self.timer.clear_interrupt();
+        if self.remaining_ticks > 0 {
+            self.schedule_next_period();
+            false
+        } else {
+            self.turn_off();
+            true
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.light.is_enabled()
+    }
+
+    pub fn light(&self) -> &L {
+        &self.light
+    }
+}
+
+pub struct UvExposure<L: UvLight = BoardUvLight, T: ExposureTimer = BoardExposureTimer> {
+    inner: SharedWithInterrupt<ExposureControl<L, T>>,
+    done: Signal<()>,
+}
+
+impl<L: UvLight, T: ExposureTimer> UvExposure<L, T> {
+    pub fn new(light: L, timer: T) -> Self {
+        Self {
+            inner: SharedWithInterrupt::new(ExposureControl::new(light, timer)),
+            done: Signal::new(),
+        }
+    }
+
+    pub fn on_interrupt(&self) {
+        let done = unsafe { self.inner.lock_from_interrupt(|ec| ec.on_interrupt()) };
+        if done {
+            self.done.signal(());
+        }
+    }
+
+    // Turns the light on for `duration`, at the intensity `pwm`. The light
+    // goes off when the future is dropped, so an aborted print doesn't leave
+    // it on.
+    pub async fn expose(&self, duration: Duration, pwm: UvPwm) {
+        let ticks = duration_to_ticks(duration);
+        if ticks == 0 {
+            return;
+        }
+
+        struct TurnOffOnDrop<'a, L: UvLight, T: ExposureTimer>(&'a UvExposure<L, T>);
+        impl<'a, L: UvLight, T: ExposureTimer> Drop for TurnOffOnDrop<'a, L, T> {
+            fn drop(&mut self) {
+                self.0.turn_off();
+            }
+        }
+
+        self.done.reset();
+        self.inner.lock(|ec| ec.start(ticks, pwm));
+        let _guard = TurnOffOnDrop(self);
+        self.done.wait().await;
+    }
+
+    pub fn turn_off(&self) {
+        self.inner.lock(|ec| ec.turn_off());
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.inner.lock(|ec| ec.is_on())
+    }
+
+    pub fn is_functional(&self) -> bool {
+        self.with_light(|light| light.is_functional())
+    }
+
+    pub fn with_light<R>(&self, mut f: impl FnMut(&L) -> R) -> R {
+        self.inner.lock(|ec| f(ec.light()))
+    }
+}
@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use embassy_stm32::{
+    peripherals as p,
+    rcc::low_level::RccPeripheral,
+    timer::low_level::Basic16bitInstance,
+};
+
+use crate::consts::uv_light::EXPOSURE_TIMER_FREQ;
+
+use super::ExposureTimer;
+
+// Any basic timer will do. TIM7 drives the stepper motor.
+impl ExposureTimer for p::TIM6 {
+    fn init(&mut self) {
+        Self::enable();
+
+        let psc = (Self::frequency().0 / EXPOSURE_TIMER_FREQ).checked_sub(1).unwrap();
+        let psc: u16 = psc.try_into().unwrap();
+        unsafe {
+            Self::regs().psc().write(|w| w.set_psc(psc));
+            // The prescaler is loaded on the next update event. Only
+            // overflows raise the update interrupt.
+            Self::regs().cr1().modify(|w| w.set_urs(embassy_stm32::pac::timer::vals::Urs::COUNTERONLY));
+            Self::regs().egr().write(|w| w.set_ug(true));
+        }
+    }
+
+    fn start(&mut self, ticks: u16) {
+        self.stop();
+        unsafe { Self::regs().arr().write(|w| w.set_arr(ticks - 1)); }
+        self.reset();
+        self.clear_update_interrupt();
+        self.enable_update_interrupt(true);
+        Basic16bitInstance::start(self);
+    }
+
+    fn stop(&mut self) {
+        self.enable_update_interrupt(false);
+        Basic16bitInstance::stop(self);
+    }
+
+    fn clear_interrupt(&mut self) {
+        self.clear_update_interrupt();
+    }
+}
@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use embassy_time::Instant;
+use heapless::Deque;
+
+use super::{UvLight, UvPwm};
+
+pub const MOCK_UV_LIGHT_MAX_EVENTS: usize = 64;
+
+#[derive(Clone, Copy, Debug)]
+pub struct UvEvent {
+    pub at: Instant,
+    pub enabled: bool,
+    pub pwm: UvPwm,
+}
+
+/// Records when the light goes on and off, to check the exposure timings.
+/// Only the most recent events are kept.
+pub struct MockUvLight {
+    enabled: bool,
+    pwm: UvPwm,
+    events: Deque<UvEvent, MOCK_UV_LIGHT_MAX_EVENTS>,
+}
+
+impl MockUvLight {
+    pub fn new() -> Self {
+        Self { enabled: false, pwm: 0, events: Deque::new() }
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = &UvEvent> {
+        self.events.iter()
+    }
+
+    pub fn pwm(&self) -> UvPwm {
+        self.pwm
+    }
+
+    /// Durations of the completed exposures, oldest first.
+    pub fn exposures(&self) -> impl Iterator<Item = embassy_time::Duration> + '_ {
+        let mut on_at = None;
+        self.events.iter().filter_map(move |e| {
+            match (e.enabled, on_at) {
+                (true, _) => { on_at = Some(e.at); None }
+                (false, Some(start)) => { on_at = None; Some(e.at - start) }
+                (false, None) => None,
+            }
+        })
+    }
+}
+
+impl UvLight for MockUvLight {
+    fn set_pwm(&mut self, pwm: UvPwm) {
+        self.pwm = pwm;
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if enabled == self.enabled {
+            return;
+        }
+        self.enabled = enabled;
+
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+        let event = UvEvent { at: Instant::now(), enabled, pwm: self.pwm };
+        // There's room, we just made some.
+        let _ = self.events.push_back(event);
+        trace!("UV light {} (pwm={})", if enabled { "on" } else { "off" }, self.pwm);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+}
@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The UV LED array under the mask LCD. It has a master switch and a PWM input
+// for the intensity. Exposures are switched on and off from a timer interrupt,
+// so that their duration doesn't depend on what the executor is busy with.
+
+mod exposure;
+pub use exposure::*;
+
+mod calibration;
+pub use calibration::*;
+
+#[cfg(not(feature="simulator"))]
+mod exposure_timer;
+
+#[cfg(feature="mono4k")]
+mod mono4k;
+#[cfg(feature="mono4k")]
+pub use mono4k::*;
+
+#[cfg(feature="saturn")]
+mod saturn;
+#[cfg(feature="saturn")]
+pub use saturn::*;
+
+#[cfg(feature="simulator")]
+pub mod simulator;
+
+mod mock;
+pub use mock::*;
+
+/// PWM duty of the UV LEDs, 0 to 255, like the CTB files describe it.
+pub type UvPwm = u8;
+
+pub trait UvLight {
+    /// Sets the intensity. Takes effect right away, even when on.
+    fn set_pwm(&mut self, pwm: UvPwm);
+    /// Switches the LEDs. Called from the exposure timer interrupt.
+    fn set_enabled(&mut self, enabled: bool);
+    fn is_enabled(&self) -> bool;
+    /// False when the LEDs can't actually be switched, e.g. when we don't
+    /// know how to drive them on this printer yet.
+    fn is_functional(&self) -> bool {
+        true
+    }
+}
+
+/// The timer that ends the exposures. It ticks at EXPOSURE_TIMER_FREQ.
+pub trait ExposureTimer {
+    /// Configures the timer, but doesn't start it.
+    fn init(&mut self);
+    /// Fires an interrupt `ticks` ticks from now. `ticks` is at least 1.
+    fn start(&mut self, ticks: u16);
+    fn stop(&mut self);
+    /// Must be called from the interrupt handler.
+    fn clear_interrupt(&mut self);
+}
+
+// The UV hardware of the printer we are building for.
+#[cfg(feature="mono4k")]
+pub type BoardUvLight = UvLed;
+#[cfg(feature="saturn")]
+pub type BoardUvLight = UnknownUvLight;
+#[cfg(not(feature="simulator"))]
+pub type BoardExposureTimer = embassy_stm32::peripherals::TIM6;
+
+#[cfg(feature="simulator")]
+pub type BoardUvLight = MockUvLight;
+#[cfg(feature="simulator")]
+pub type BoardExposureTimer = simulator::VirtualExposureTimer;
@@ -0,0 +1,51 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use embassy_stm32::pwm::{simple_pwm::{PwmPin, SimplePwm}, Channel};
+use embassy_stm32::gpio::{Output, Level, Speed};
+use embassy_stm32::time::Hertz;
+use embassy_stm32::peripherals as p;
+
+use crate::consts::uv_light::*;
+
+use super::{UvLight, UvPwm};
+
+pub struct UvLed {
+    enable: Output<'static, p::PB7>,
+    pwm: SimplePwm<'static, p::TIM5>,
+}
+
+impl UvLed {
+    pub fn new(
+        pwm: p::PA1,
+        enable: p::PB7, // Master switch
+        pwm_timer: p::TIM5, // TIM2 is taken by the stepper driver vref.
+    ) -> Self {
+        let enable = Output::new(enable, Level::Low, Speed::Low);
+
+        let mut pwm = SimplePwm::new(pwm_timer, None, Some(PwmPin::new_ch2(pwm)), None, None, Hertz::khz(PWM_FREQ_KHZ));
+        pwm.enable(Channel::Ch2);
+
+        let mut this = Self { enable, pwm };
+        this.set_pwm(DEFAULT_PWM);
+        this
+    }
+}
+
+impl UvLight for UvLed {
+    fn set_pwm(&mut self, pwm: UvPwm) {
+        let duty = (self.pwm.get_max_duty() as u32) * pwm as u32 / UvPwm::MAX as u32;
+        self.pwm.set_duty(Channel::Ch2, duty as u16);
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if enabled {
+            self.enable.set_high();
+        } else {
+            self.enable.set_low();
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enable.is_set_high()
+    }
+}
@@ -0,0 +1,34 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use super::{UvLight, UvPwm};
+
+// We don't know yet how the saturn drives its UV LEDs. Prints refuse to
+// start until we do, see is_functional().
+pub struct UnknownUvLight {
+    enabled: bool,
+}
+
+impl UnknownUvLight {
+    pub fn new() -> Self {
+        Self { enabled: false }
+    }
+}
+
+impl UvLight for UnknownUvLight {
+    fn set_pwm(&mut self, _pwm: UvPwm) {}
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if enabled && !self.enabled {
+            warn!("The UV light is not supported on this printer");
+        }
+        self.enabled = enabled;
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn is_functional(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The exposure timer of the simulator runs on its own thread, like the step
+// timer. The light itself is a MockUvLight.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::consts::uv_light::EXPOSURE_TIMER_FREQ;
+
+use super::ExposureTimer;
+
+// When the next interrupt fires, in microseconds since the thread started.
+// u64::MAX when the timer is stopped.
+static DEADLINE_US: AtomicU64 = AtomicU64::new(u64::MAX);
+// Microseconds since the thread started, as seen by the thread.
+static NOW_US: AtomicU64 = AtomicU64::new(0);
+
+pub struct VirtualExposureTimer;
+
+impl VirtualExposureTimer {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Runs `on_interrupt` when the timer expires, on a dedicated thread.
+    pub fn spawn_interrupt_thread(mut on_interrupt: impl FnMut() + Send + 'static) {
+        std::thread::spawn(move || {
+            let boot = Instant::now();
+            loop {
+                let now = boot.elapsed().as_micros() as u64;
+                NOW_US.store(now, Ordering::Relaxed);
+
+                let deadline = DEADLINE_US.load(Ordering::Acquire);
+                if now >= deadline {
+                    on_interrupt();
+                    continue;
+                }
+
+                // Good enough for a millisecond accuracy.
+                let sleep_us = deadline.saturating_sub(now).min(200);
+                std::thread::sleep(Duration::from_micros(sleep_us));
+            }
+        });
+    }
+}
+
+impl ExposureTimer for VirtualExposureTimer {
+    fn init(&mut self) {}
+
+    fn start(&mut self, ticks: u16) {
+        let us = ticks as u64 * 1_000_000 / EXPOSURE_TIMER_FREQ as u64;
+        DEADLINE_US.store(NOW_US.load(Ordering::Relaxed) + us, Ordering::Release);
+    }
+
+    fn stop(&mut self) {
+        DEADLINE_US.store(u64::MAX, Ordering::Release);
+    }
+
+    fn clear_interrupt(&mut self) {
+        // The interrupt fires once per start().
+        DEADLINE_US.store(u64::MAX, Ordering::Release);
+    }
+}
@@ -4,32 +4,53 @@ use crate::consts::zaxis::hardware::*;
 
 // We describe distances in mm as integers, in number of stepper moter steps to
 // not loose accuracy with floating points.
+// The arithmetic saturates instead of overflowing. Positions are far from the
+// i32 limits, a saturated value is a bug, but a safe one.
 
-#[derive(PartialEq, PartialOrd, Clone, Copy)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
 pub struct Steps(pub i32);
 
 const STEPS_PER_MM: f32 = (DRIVER_MICROSTEPS * FULL_STEPS_PER_REVOLUTION) as f32 / SCREW_THREAD_PITCH_MM;
 
-impl Steps {
-    pub const MIN: Self = Self(i32::MIN/2);
-    pub const MAX: Self = Self(i32::MAX/2);
+// For the integer conversions from microns.
+const STEPS_PER_REVOLUTION: i64 = (DRIVER_MICROSTEPS * FULL_STEPS_PER_REVOLUTION) as i64;
+const SCREW_THREAD_PITCH_UM: i64 = (SCREW_THREAD_PITCH_MM * 1000.0) as i64;
+
+// Rounds to the nearest integer. f32::round() is not available in core.
+fn round(v: f32) -> i32 {
+    // `as` saturates
+    if v >= 0.0 { (v + 0.5) as i32 } else { (v - 0.5) as i32 }
+}
 
+impl Steps {
     pub fn as_mm(self) -> f32 {
         (self.0 as f32) / STEPS_PER_MM
     }
+
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Steps)
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Steps)
+    }
+
+    pub fn abs(self) -> Self {
+        Steps(self.0.saturating_abs())
+    }
 }
 
 impl core::ops::Add for Steps {
     type Output = Steps;
     fn add(self, rhs: Self) -> Self::Output {
-        Steps(self.0 + rhs.0)
+        Steps(self.0.saturating_add(rhs.0))
     }
 }
 
 impl core::ops::Sub for Steps {
     type Output = Steps;
     fn sub(self, rhs: Self) -> Self::Output {
-        Steps(self.0 - rhs.0)
+        Steps(self.0.saturating_sub(rhs.0))
     }
 }
 
@@ -37,21 +58,78 @@ impl core::ops::Neg for Steps {
     type Output = Steps;
 
     fn neg(self) -> Self::Output {
-        Steps(-self.0)
+        Steps(self.0.saturating_neg())
+    }
+}
+
+// A distance in microns, for what the print files describe: layer heights,
+// lift distances. Positions are computed in microns and converted once to
+// steps, so that 0.01mm layers don't accumulate rounding errors over
+// thousands of layers.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Default)]
+pub struct Distance(pub i32);
+
+impl Distance {
+    pub fn from_um(um: i32) -> Self {
+        Self(um)
+    }
+
+    pub fn from_mm(mm: f32) -> Self {
+        Self(round(mm * 1000.0))
+    }
+
+    pub fn um(self) -> i32 {
+        self.0
+    }
+
+    pub fn as_mm(self) -> f32 {
+        self.0 as f32 / 1000.0
+    }
+
+    // Rounded to the nearest step, with integer arithmetic.
+    pub fn steps(self) -> Steps {
+        let num = self.0 as i64 * STEPS_PER_REVOLUTION;
+        let half = SCREW_THREAD_PITCH_UM / 2;
+        let steps = if num >= 0 { (num + half) / SCREW_THREAD_PITCH_UM } else { (num - half) / SCREW_THREAD_PITCH_UM };
+        Steps(steps.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+    }
+}
+
+impl core::ops::Add for Distance {
+    type Output = Distance;
+    fn add(self, rhs: Self) -> Self::Output {
+        Distance(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl core::ops::Sub for Distance {
+    type Output = Distance;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Distance(self.0.saturating_sub(rhs.0))
+    }
+}
+
+// E.g. layer_height * layer_index
+impl core::ops::Mul<u32> for Distance {
+    type Output = Distance;
+    fn mul(self, rhs: u32) -> Self::Output {
+        let v = self.0 as i64 * rhs as i64;
+        Distance(v.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
     }
 }
 
 pub mod prelude {
     use super::*;
-    pub use super::Steps;
+    pub use super::{Steps, Distance};
 
     pub trait StepsExt {
         fn mm(self) -> Steps;
     }
 
     impl StepsExt for f32 {
+        // Rounded to the nearest step
         fn mm(self) -> Steps {
-            Steps((self * STEPS_PER_MM) as i32)
+            Steps(round(self * STEPS_PER_MM))
         }
     }
 
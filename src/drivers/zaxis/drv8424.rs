@@ -10,11 +10,7 @@ use embassy_stm32::peripherals as p;
 
 use crate::consts::zaxis::hardware::*;
 
-#[derive(PartialEq, Clone, Copy)]
-pub enum Direction {
-    Up,
-    Down,
-}
+use super::{Direction, StepperDriver, CurrentLevel};
 
 pub struct Drv8424 {
     step: Output<'static, p::PE5>,
@@ -22,7 +18,8 @@ pub struct Drv8424 {
     enable: Output<'static, p::PE6>,
     mode0: Flex<'static, p::PC3>,
     mode1: Flex<'static, p::PC0>,
-    pub step_multiplier: u32,
+    vref: SimplePwm<'static, p::TIM2>,
+    step_multiplier: u32,
 }
 
 impl Drv8424 {
@@ -69,17 +66,30 @@ impl Drv8424 {
         core::mem::forget(Output::new(decay1, Level::Low, Speed::Low));
 
         // vref is used to set the amount of current the motor receives.
-        let mut pwm = SimplePwm::new(pwm_timer, None, None, None, Some(PwmPin::new_ch4(vref)), Hertz::khz(100));
-        pwm.set_duty(Channel::Ch4, ((pwm.get_max_duty() as u32) * MOTOR_CURRENT_PERCENT / 100) as u16);
-        pwm.enable(Channel::Ch4);
+        // The DRV8424 filters the PWM into an analog voltage.
+        let mut vref = SimplePwm::new(pwm_timer, None, None, None, Some(PwmPin::new_ch4(vref)), Hertz::khz(100));
+        vref.enable(Channel::Ch4);
 
         let step_multiplier = 0;
 
-        Self { dir, step, enable, mode0, mode1, step_multiplier }
+        let mut this = Self { dir, step, enable, mode0, mode1, vref, step_multiplier };
+        this.set_current(CurrentLevel::Run);
+        this
+    }
+
+    fn set_current_percent(&mut self, percent: u32) {
+        let duty = (self.vref.get_max_duty() as u32) * percent / 100;
+        self.vref.set_duty(Channel::Ch4, duty as u16);
     }
+}
+
+impl StepperDriver for Drv8424 {
+    const HAS_CURRENT_CONTROL: bool = true;
+    // Step multiplier 4 (1/64) is not available, see set_step_multiplier().
+    const SUPPORTED_MULTIPLIERS: &'static [u32] = &[1, 2, 8, 16, 32, 64, 128, 256];
 
     // Note: wait at least 200ns before STEP changes after changing the microstepping
-    pub fn set_step_multiplier(&mut self, step_multiplier: u32) {
+    fn set_step_multiplier(&mut self, step_multiplier: u32) {
         // Multip.   | Mode0     | Mode1     | Step mode
         // ----------|-----------|-----------|------------
         //      256  | 0         |  0        | Full step (100% current)
@@ -115,15 +125,19 @@ impl Drv8424 {
         }
     }
 
+    fn get_step_multiplier(&self) -> u32 {
+        self.step_multiplier
+    }
+
     // Note: wait at least 200ns before STEP changes after changing the direction
-    pub fn set_direction(&mut self, direction: Direction) {
+    fn set_direction(&mut self, direction: Direction) {
         match direction {
             Direction::Up => self.dir.set_high(),
             Direction::Down => self.dir.set_low(),
         }
     }
 
-    pub fn get_direction(&self) -> Direction {
+    fn get_direction(&self) -> Direction {
         match self.dir.is_set_high() {
             true  => Direction::Up,
             false => Direction::Down,
@@ -132,7 +146,7 @@ impl Drv8424 {
 
     // Note: f() must take at least 1us to complete. Also, two consecutive calls
     // to do_step() should also be separated by 1us.
-    pub fn do_step<R>(&mut self, mut f: impl FnMut(&mut Self) -> R) -> R {
+    fn do_step<R>(&mut self, mut f: impl FnMut(&mut Self) -> R) -> R {
         // The stepper motor advances when the `step` pin rises from low to high.
         // We have to hold the `step` pin high for at least 1us according to the datasheet.
         // Might as well do something useful during this time
@@ -143,16 +157,24 @@ impl Drv8424 {
     }
 
     // Note: STEP can only be toggled after 5us
-    pub fn enable(&mut self) {
+    fn enable(&mut self) {
         self.enable.set_high();
     }
 
-    pub fn disable(&mut self) {
+    fn disable(&mut self) {
         self.step.set_low();
         self.enable.set_low();
     }
 
-    pub fn is_enabled(&self) -> bool {
+    fn is_enabled(&self) -> bool {
         self.enable.is_set_high()
     }
+
+    fn set_current(&mut self, level: CurrentLevel) {
+        match level {
+            CurrentLevel::Run => self.set_current_percent(MOTOR_RUN_CURRENT_PERCENT),
+            CurrentLevel::Hold => self.set_current_percent(MOTOR_HOLD_CURRENT_PERCENT),
+            CurrentLevel::Off => {}
+        }
+    }
 }
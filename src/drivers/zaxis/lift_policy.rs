@@ -0,0 +1,132 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The peel force grows with the area that was cured in the layer. Small layers
+// can lift fast, large flat ones must peel gently. The policy is a curve that
+// gives the lift height, the peel speed, and the wait time before the exposure
+// for a given cured area. Between two points, values are interpolated. The
+// curve comes from the settings, or DEFAULT_CURVE when there's none.
+
+use embassy_time::Duration;
+
+use heapless::Vec;
+
+use crate::consts::zaxis::lift_policy::*;
+use crate::drivers::lcd::Color8;
+
+use super::{prelude::*, LiftRetract};
+
+pub const MAX_LIFT_POLICY_POINTS: usize = 8;
+
+// Counts the pixels that get cured while a layer is decoded. It gets the same
+// pixel runs as the LCD.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CuredArea {
+    pixels: u32,
+}
+
+impl CuredArea {
+    pub fn add_run(&mut self, color: Color8, repeat: u32) {
+        if color != 0 {
+            self.pixels += repeat;
+        }
+    }
+
+    pub fn pixels(&self) -> u32 {
+        self.pixels
+    }
+
+    pub fn mm2(&self, pixel_area_mm2: f32) -> f32 {
+        self.pixels as f32 * pixel_area_mm2
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LiftPoint {
+    pub area_mm2: f32,
+    // Total lift, slow and fast stages.
+    pub lift_height_mm: f32,
+    // Speed of the slow lift stage.
+    pub peel_speed_mm_per_sec: f32,
+    // Rest before the exposure, for the resin to settle.
+    pub wait_sec: f32,
+}
+
+impl LiftPoint {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            area_mm2: lerp(self.area_mm2, other.area_mm2),
+            lift_height_mm: lerp(self.lift_height_mm, other.lift_height_mm),
+            peel_speed_mm_per_sec: lerp(self.peel_speed_mm_per_sec, other.peel_speed_mm_per_sec),
+            wait_sec: lerp(self.wait_sec, other.wait_sec),
+        }
+    }
+}
+
+// How the plate moves between two layers.
+#[derive(Clone, Copy)]
+pub struct LayerMotion {
+    pub lift_retract: LiftRetract,
+    pub wait: Duration,
+}
+
+pub struct LiftPolicy {
+    // Sorted by area
+    points: Vec<LiftPoint, MAX_LIFT_POLICY_POINTS>,
+}
+
+impl LiftPolicy {
+    // Fails if there are no points, too many points, or if the areas are
+    // not increasing.
+    pub fn new(points: &[LiftPoint]) -> Result<Self, ()> {
+        if points.is_empty() || points.windows(2).any(|p| p[0].area_mm2 >= p[1].area_mm2) {
+            return Err(());
+        }
+        let points = Vec::from_slice(points)?;
+        Ok(Self { points })
+    }
+
+    pub fn at(&self, area_mm2: f32) -> LiftPoint {
+        let first = &self.points[0];
+        let last = &self.points[self.points.len()-1];
+
+        if area_mm2 <= first.area_mm2 {
+            return *first;
+        }
+
+        for p in self.points.windows(2) {
+            let (a, b) = (&p[0], &p[1]);
+            if area_mm2 <= b.area_mm2 {
+                return a.lerp(b, (area_mm2 - a.area_mm2) / (b.area_mm2 - a.area_mm2));
+            }
+        }
+
+        *last
+    }
+
+    // The slow lift stage keeps the distance of `base`, the fast stage covers
+    // the rest of the lift height. The retract stages are unchanged.
+    pub fn layer_motion(&self, area_mm2: f32, base: &LiftRetract) -> LayerMotion {
+        let point = self.at(area_mm2);
+
+        let mut lift_retract = *base;
+        lift_retract.lift_slow.params.max_speed = point.peel_speed_mm_per_sec.mm();
+        let fast_distance = point.lift_height_mm.mm() - lift_retract.lift_slow.distance;
+        lift_retract.lift_fast.distance = if fast_distance.0 > 0 { fast_distance } else { Steps(0) };
+
+        let wait = Duration::from_millis((point.wait_sec * 1000.0) as u64);
+
+        LayerMotion { lift_retract, wait }
+    }
+}
+
+impl Default for LiftPolicy {
+    fn default() -> Self {
+        let points: Vec<LiftPoint, MAX_LIFT_POLICY_POINTS> = DEFAULT_CURVE.iter()
+            .map(|&(area_mm2, lift_height_mm, peel_speed_mm_per_sec, wait_sec)|
+                LiftPoint { area_mm2, lift_height_mm, peel_speed_mm_per_sec, wait_sec })
+            .collect();
+        Self::new(&points).expect("Invalid DEFAULT_CURVE")
+    }
+}
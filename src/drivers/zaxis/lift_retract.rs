@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Between two layers, the build plate lifts to peel the cured layer from the
+// FEP, and comes back down to the next layer. Slicers describe this in two
+// stages (TSMC): the lift starts slowly while peeling, and continues quickly
+// once the layer is free. The retract travels quickly, and slows down when
+// approaching the vat to let the resin flow.
+
+use crate::consts::zaxis::motion_control::*;
+
+use super::{prelude::*, Segment, MoveParams, MoveError, Event, MotionControlAsync, Profile};
+
+#[derive(Clone, Copy)]
+pub struct Stage {
+    pub distance: Steps,
+    pub params: MoveParams,
+}
+
+impl Stage {
+    pub fn new(distance_mm: f32, speed_mm_per_sec: f32) -> Self {
+        Self {
+            distance: distance_mm.mm(),
+            params: MoveParams::new(speed_mm_per_sec.mm()),
+        }
+    }
+
+    // With the S-curve profile. For peeling, and approaching the vat.
+    pub fn gentle(distance_mm: f32, speed_mm_per_sec: f32) -> Self {
+        let mut stage = Self::new(distance_mm, speed_mm_per_sec);
+        stage.params.profile = Profile::s_curve(MAX_JERK.mm());
+        stage
+    }
+
+    fn segment(&self, target: Steps) -> Segment {
+        Segment::new(target, self.params)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct LiftRetract {
+    // Peeling the layer, then traveling up.
+    pub lift_slow: Stage,
+    pub lift_fast: Stage,
+    // Traveling down, then approaching the next layer. The distance of
+    // retract_fast is ignored, it covers what retract_slow doesn't.
+    pub retract_fast: Stage,
+    pub retract_slow: Stage,
+}
+
+// Lifts from the current position, and retracts to `target`. The two lift
+// stages are chained without stopping, so are the two retract stages.
+// Resolves when the plate has reached `target`. Fails without moving if the
+// plate is not homed, or if the moves would go past the soft limits.
+pub async fn lift_and_retract(mc: &mut MotionControlAsync, lr: &LiftRetract, target: Steps) -> Result<(), MoveError> {
+    // The motion queue must be empty.
+    mc.wait(Event::Idle).await;
+    start_lift_and_retract(mc, lr, target)?;
+    mc.wait(Event::Idle).await;
+    Ok(())
+}
+
+// Queues the moves of lift_and_retract(), and returns where the slow lift
+// ends. The plate must be idle.
+pub(super) fn start_lift_and_retract(mc: &mut MotionControlAsync, lr: &LiftRetract, target: Steps) -> Result<Steps, MoveError> {
+    let peel_end = mc.get_current_position() + lr.lift_slow.distance;
+    let top = peel_end + lr.lift_fast.distance;
+    let approach_start = target + lr.retract_slow.distance;
+    let approach_start = if approach_start > top { top } else { approach_start };
+
+    let segments = [
+        lr.lift_slow.segment(peel_end),
+        lr.lift_fast.segment(top),
+        lr.retract_fast.segment(approach_start),
+        lr.retract_slow.segment(target),
+    ];
+
+    for segment in &segments {
+        mc.check_target(segment.target)?;
+    }
+
+    for segment in segments {
+        // The queue is empty, there's room.
+        mc.enqueue(segment)?;
+    }
+
+    Ok(peel_end)
+}
@@ -1,15 +1,43 @@
 mod step_generator;
 pub use step_generator::*;
 
+mod stepper_driver;
+pub use stepper_driver::*;
+
 mod motion_control;
 pub use motion_control::*;
 
+#[cfg(not(feature="simulator"))]
 mod sensor;
+#[cfg(not(feature="simulator"))]
 pub use sensor::*;
 
+#[cfg(feature="mono4k")]
 mod drv8424;
+#[cfg(feature="mono4k")]
 pub use drv8424::*;
 
+#[cfg(feature="saturn")]
+mod step_dir;
+#[cfg(feature="saturn")]
+pub use step_dir::*;
+
+#[cfg(not(feature="simulator"))]
+mod step_timer;
+
+#[cfg(any(test, feature="simulator"))]
+mod recording;
+#[cfg(any(test, feature="simulator"))]
+pub use recording::*;
+
+#[cfg(feature="simulator")]
+pub mod validation;
+
+#[cfg(feature="simulator")]
+pub mod simulator;
+#[cfg(feature="simulator")]
+pub use simulator::BottomSensor;
+
 mod distance;
 pub use distance::*;
 pub use distance::prelude;
@@ -19,3 +47,28 @@ pub use origin_calibration::*;
 
 mod motion_control_async;
 pub use motion_control_async::*;
+
+mod lift_retract;
+pub use lift_retract::*;
+
+mod peel_control;
+pub use peel_control::*;
+
+mod lift_policy;
+pub use lift_policy::*;
+
+mod telemetry;
+pub use telemetry::*;
+
+// The Z-axis hardware of the printer we are building for.
+#[cfg(feature="mono4k")]
+pub type BoardStepper = Drv8424;
+#[cfg(feature="saturn")]
+pub type BoardStepper = StepDirDriver;
+#[cfg(not(feature="simulator"))]
+pub type BoardStepTimer = embassy_stm32::peripherals::TIM7;
+
+#[cfg(feature="simulator")]
+pub type BoardStepper = simulator::VirtualStepper;
+#[cfg(feature="simulator")]
+pub type BoardStepTimer = simulator::VirtualStepTimer;
@@ -1,75 +1,152 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use embassy_stm32::pac::timer::regs::Arr16;
-use embassy_stm32::peripherals as p;
-use embassy_stm32::rcc::low_level::RccPeripheral;
-use embassy_stm32::time::Hertz;
+use heapless::Deque;
 
-use embassy_stm32::timer::low_level::{Basic16bitInstance, GeneralPurpose16bitInstance};
-
-use super::step_generator::StepGenerator;
+use super::step_generator::{StepGenerator, Profile, sqrt};
+use super::telemetry::MOTION_TELEMETRY;
 
 use crate::consts::zaxis::{
     stepper::*,
     motion_control::*,
 };
 
-type StepTimer = p::TIM7; // Any timer will do.
+use super::{
+    prelude::*,
+    Direction, StepperDriver, StepTimer, CurrentLevel,
+    BoardStepper, BoardStepTimer,
+};
 
-trait TimerExt {
-    fn set_psc(psc: u16);
-    fn set_arr(psc: u16);
+// Number of segments that can be queued after the one being executed.
+pub const MOTION_QUEUE_LEN: usize = 8;
+
+// How a move should be done. Speeds are in steps/s, accelerations in
+// steps/s^2.
+#[derive(Clone, Copy)]
+pub struct MoveParams {
+    pub max_speed: Steps,
+    pub acceleration: Steps,
+    pub deceleration: Steps,
+    pub profile: Profile,
 }
 
-impl TimerExt for StepTimer {
-    fn set_psc(psc: u16) {
-        unsafe { Self::regs().psc().write(|w| w.set_psc(psc)) }
+impl MoveParams {
+    // With the default acceleration and deceleration, and the trapezoidal
+    // profile.
+    pub fn new(max_speed: Steps) -> Self {
+        Self {
+            max_speed,
+            acceleration: MAX_ACCELERATION.mm(),
+            deceleration: MAX_DECELERATION.mm(),
+            profile: Profile::Trapezoidal,
+        }
     }
 
-    fn set_arr(arr: u16) {
-        unsafe { Self::regs().arr().write(|w| w.set_arr(arr)); }
+    // With the S-curve profile, for moves that must not shake the plate, like
+    // peeling a layer.
+    pub fn gentle(max_speed: Steps) -> Self {
+        Self { profile: Profile::s_curve(MAX_JERK.mm()), ..Self::new(max_speed) }
     }
 }
 
+impl Default for MoveParams {
+    fn default() -> Self {
+        Self::new(MAX_SPEED.mm())
+    }
+}
 
-use super::{
-    prelude::*,
-    drv8424::{Drv8424, Direction},
-};
+// A move to `target`, as part of a sequence of moves.
+#[derive(Clone, Copy)]
+pub struct Segment {
+    pub target: Steps,
+    pub params: MoveParams,
+}
+
+impl Segment {
+    pub fn new(target: Steps, params: MoveParams) -> Self {
+        Self { target, params }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MoveError {
+    // Absolute moves need calibrate_origin() to have succeeded.
+    NotHomed,
+    // The target is outside of the soft limits.
+    OutOfLimits,
+    QueueFull,
+}
 
-pub struct MotionControl {
-    drv8424: Drv8424,
-    step_timer: p::TIM7,
+pub struct MotionControl<D: StepperDriver = BoardStepper, T: StepTimer = BoardStepTimer> {
+    driver: D,
+    step_timer: T,
     stepgen: StepGenerator,
     current_position: Steps,
+    // Target of the move being executed. With queued segments, it's the end
+    // of the current segment.
     target: Steps,
+    // Segments that follow the current move. When a segment goes in the same
+    // direction as the previous one, we go from one to the other without
+    // stopping.
+    queue: Deque<Segment, MOTION_QUEUE_LEN>,
+    homed: bool,
+    // Soft limits, only enforced when homed.
+    min_position: Steps,
+    max_position: Steps,
+    // Play in the lead screw nut. When reversing, the motor turns this much
+    // before the plate moves.
+    backlash: u32,
+    // Motor position minus plate position, between 0 (the nut pushes down)
+    // and backlash (the nut pushes up).
+    backlash_offset: u32,
+    moving: bool,
+    // Run while moving. When idle, we hold the plate in place once homed, and
+    // let the motor go otherwise.
+    current_level: CurrentLevel,
 }
 
-impl MotionControl {
+impl<D: StepperDriver, T: StepTimer> MotionControl<D, T> {
     pub fn new(
-        drv8424: Drv8424,
-        mut step_timer: StepTimer,
+        driver: D,
+        mut step_timer: T,
     ) -> Self {
         let stepgen = StepGenerator::new(
             MAX_ACCELERATION.mm().0 as f32,
             MAX_DECELERATION.mm().0 as f32,
             MAX_SPEED.mm().0 as f32,
+            D::SUPPORTED_MULTIPLIERS,
         );
 
-        StepTimer::enable();
-        step_timer.start();
-
-        let psc = (StepTimer::frequency().0 / STEP_TIMER_FREQ).checked_sub(1).unwrap();
-        StepTimer::set_psc(psc.try_into().unwrap());
+        step_timer.init(STEP_TIMER_FREQ);
 
         let current_position = Steps(0);
         let target = Steps(0);
 
-        Self { drv8424, step_timer, stepgen, current_position, target }
+        let queue = Deque::new();
+        let homed = false;
+        let min_position = MIN_POSITION_MM.mm();
+        let max_position = MAX_POSITION_MM.mm();
+
+        let backlash = 0;
+        let backlash_offset = 0;
+
+        let moving = false;
+        let current_level = CurrentLevel::Off;
+
+        Self {
+            driver, step_timer, stepgen, current_position, target, queue,
+            homed, min_position, max_position, backlash, backlash_offset,
+            moving, current_level,
+        }
     }
 
     pub fn on_interrupt(&mut self) {
-        self.step_timer.clear_update_interrupt();
+        self.step_timer.clear_interrupt();
+
+        // All the steps of the current segment have been scheduled. The step
+        // we are about to do is the last one.
+        if self.stepgen.get_remaining_steps() == 0 {
+            self.chain_next_segment();
+        }
 
         let next_delay = self.do_step(|stepgen| {
             // We do some useful things while we wait for the 1us delay to pass
@@ -80,7 +157,7 @@ impl MotionControl {
         });
 
         if let Some((delay_us, multiplier)) = next_delay {
-            self.drv8424.set_step_multiplier(multiplier);
+            self.driver.set_step_multiplier(multiplier);
 
             let arr = if delay_us >= u16::MAX as f32 {
                 u16::MAX
@@ -90,13 +167,20 @@ impl MotionControl {
                 ((delay_us + 0.5) as u16).saturating_sub(1)
             };
 
-            StepTimer::set_arr(arr);
+            self.step_timer.set_auto_reload(arr);
+            MOTION_TELEMETRY.record(self.current_position, arr, multiplier,
+                self.stepgen.end_approaching());
             // Note: if cnt > arr at this point, an interrupt event is generated
             // immediately. This is what we want.
             // But it should not happen because MIN_DELAY_VALUE == 15.
             // This whole interrupt routine takes at most 300 CPU cycles to run.
             // That's 2.5us. That's a x6 margin.
+        } else if let Some(segment) = self.queue.pop_front() {
+            MOTION_TELEMETRY.record(self.current_position, 0, 0, true);
+            // The next segment goes the other way, we had to stop first.
+            self.start_segment(segment);
         } else {
+            MOTION_TELEMETRY.record(self.current_position, 0, 0, true);
             self.hard_stop();
         }
     }
@@ -104,22 +188,139 @@ impl MotionControl {
     // If max_speed is None, it goes back to default.
     pub fn set_max_speed(&mut self, max_speed: Steps) {
         self.stepgen.set_max_speed(max_speed.0 as f32);
+        // We can't leave the current segment faster than its max speed.
+        if !self.queue.is_empty() {
+            self.plan_exit_speed();
+        }
     }
 
     pub fn get_max_speed(&self) -> Steps {
         Steps(self.stepgen.get_max_speed() as i32)
     }
 
+
     pub fn get_current_position(&self) -> Steps {
         self.current_position
     }
 
+    // Where the plate comes to rest, at the end of the queued segments.
+    pub fn get_target(&self) -> Steps {
+        self.queue.back().map(|s| s.target).unwrap_or(self.target)
+    }
+
+    // The origin is only meaningful once homed. calibrate_origin() sets it.
+    pub fn set_homed(&mut self, homed: bool) {
+        self.homed = homed;
+        if !self.moving {
+            self.set_current_level(self.idle_current_level());
+        }
+    }
+
+    pub fn is_homed(&self) -> bool {
+        self.homed
+    }
+
+    // The backlash is taken up at the beginning of moves that reverse the
+    // direction. The reported position is the one of the plate.
+    pub fn set_backlash(&mut self, backlash: Steps) {
+        self.backlash = backlash.0.max(0) as u32;
+        self.backlash_offset = self.backlash_offset.min(self.backlash);
+    }
+
+    pub fn get_backlash(&self) -> Steps {
+        Steps(self.backlash as i32)
+    }
+
+    pub fn set_limits(&mut self, min_position: Steps, max_position: Steps) {
+        self.min_position = min_position;
+        self.max_position = max_position;
+    }
+
+    pub fn get_limits(&self) -> (Steps, Steps) {
+        (self.min_position, self.max_position)
+    }
+
+    // Absolute targets are refused until we are homed.
+    pub fn check_target(&self, target: Steps) -> Result<(), MoveError> {
+        if !self.homed {
+            Err(MoveError::NotHomed)
+        } else if target < self.min_position || target > self.max_position {
+            Err(MoveError::OutOfLimits)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Relative moves are always possible. When homed, the target is brought
+    // back within the limits. This is what we want when jogging.
+    fn relative_target(&self, steps: Steps) -> Steps {
+        let target = self.current_position + steps;
+        if !self.homed {
+            target
+        } else if target < self.min_position {
+            self.min_position
+        } else if target > self.max_position {
+            self.max_position
+        } else {
+            target
+        }
+    }
+
     // relative to current position
     pub fn set_target_relative(&mut self, steps: Steps) {
-        self.set_target(self.current_position + steps);
+        let target = self.relative_target(steps);
+        self.set_target_unchecked(target);
+    }
+
+    // Moves to target with the given params, discarding any queued segment.
+    pub fn move_to(&mut self, target: Steps, params: &MoveParams) -> Result<(), MoveError> {
+        self.check_target(target)?;
+        self.move_to_unchecked(target, params);
+        Ok(())
+    }
+
+    pub fn move_relative(&mut self, steps: Steps, params: &MoveParams) {
+        let target = self.relative_target(steps);
+        self.move_to_unchecked(target, params);
+    }
+
+    // Moves in the given direction until stop() is called, e.g. when jogging.
+    // When homed, the plate stops at the soft limits. Otherwise, the position
+    // is unknown, and it covers at most JOG_DISTANCE, like a jog used to.
+    pub fn move_until_stopped(&mut self, direction: Direction, params: &MoveParams) {
+        let travel = if self.homed {
+            self.max_position - self.min_position
+        } else {
+            JOG_DISTANCE_MM.mm()
+        };
+        let steps = match direction {
+            Direction::Up => travel,
+            Direction::Down => -travel,
+        };
+        self.move_relative(steps, params);
+    }
+
+    fn move_to_unchecked(&mut self, target: Steps, params: &MoveParams) {
+        self.queue.clear();
+        self.apply_params(params);
+        self.stepgen.set_exit_speed(0.0);
+        self.start_move(target);
     }
 
-    pub fn set_target(&mut self, target: Steps) {
+    // Moves to target, discarding any queued segment. The max speed is the
+    // one of the previous move, the other parameters are the defaults.
+    pub fn set_target(&mut self, target: Steps) -> Result<(), MoveError> {
+        self.check_target(target)?;
+        self.set_target_unchecked(target);
+        Ok(())
+    }
+
+    fn set_target_unchecked(&mut self, target: Steps) {
+        let params = MoveParams::new(self.get_max_speed());
+        self.move_to_unchecked(target, &params);
+    }
+
+    fn start_move(&mut self, target: Steps) {
         self.target = target;
         let steps = target - self.current_position;
 
@@ -127,61 +328,251 @@ impl MotionControl {
             return;
         }
 
+        // The motor turns more than the plate moves when the nut has to
+        // cross the backlash first.
         let (dir, steps) = if steps.0 > 0 {
-            (Direction::Up, steps.0 as u32)
+            (Direction::Up, steps.0 as u32 + (self.backlash - self.backlash_offset))
         } else {
-            (Direction::Down, -steps.0 as u32)
+            (Direction::Down, -steps.0 as u32 + self.backlash_offset)
         };
 
-        self.drv8424.set_direction(dir);
-        self.drv8424.set_step_multiplier(1);
-        self.drv8424.enable();
+        self.driver.set_direction(dir);
+        self.driver.set_step_multiplier(1);
+        self.set_current_level(CurrentLevel::Run);
+        self.moving = true;
 
         // steps-1 because we are going to do the first step immedately.
         self.stepgen.set_remaining_steps(steps-1);
 
         // We need to hold the enable pin high for 5us before we can start
         // stepping the motor. That's from the DRV8424 datasheet.
-        StepTimer::set_arr((5 * STEP_TIMER_FREQ / 1_000_000) as u16);
+        self.step_timer.set_auto_reload((5 * STEP_TIMER_FREQ / 1_000_000) as u16);
+
+        self.step_timer.reset_counter();
+
+        self.step_timer.enable_interrupt(true);
+    }
+
+    // Adds a segment to the motion queue. If we are idle, the segment starts
+    // immediately.
+    pub fn enqueue(&mut self, segment: Segment) -> Result<(), MoveError> {
+        self.check_target(segment.target)?;
+
+        if self.is_idle() {
+            self.start_segment(segment);
+            return Ok(());
+        }
+
+        // Segments that don't move would get in the way of chaining.
+        let previous_target = self.queue.back().map(|s| s.target).unwrap_or(self.target);
+        if segment.target == previous_target {
+            return Ok(());
+        }
+
+        self.queue.push_back(segment).map_err(|_| MoveError::QueueFull)?;
+        if self.queue.len() == 1 {
+            // The current segment may not have to stop anymore.
+            self.plan_exit_speed();
+        }
+        Ok(())
+    }
 
-        self.step_timer.reset();
+    pub fn queue_len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn apply_params(&mut self, params: &MoveParams) {
+        self.stepgen.set_max_speed(params.max_speed.0 as f32);
+        self.stepgen.set_acceleration(params.acceleration.0 as f32);
+        self.stepgen.set_deceleration(params.deceleration.0 as f32);
+        self.stepgen.set_profile(params.profile);
+    }
+
+    fn start_segment(&mut self, segment: Segment) {
+        self.apply_params(&segment.params);
+        self.stepgen.set_exit_speed(0.0);
+        self.start_move(segment.target);
+        self.plan_exit_speed();
+    }
 
-        self.step_timer.enable_update_interrupt(true);
+    // Whether we can go from the current segment into `next` without stopping.
+    fn continues_into(&self, next: &Segment) -> bool {
+        let steps = next.target - self.target;
+        match self.driver.get_direction() {
+            Direction::Up => steps.0 > 0,
+            Direction::Down => steps.0 < 0,
+        }
+    }
+
+    // Sets the speed at which we should leave the current segment. We only
+    // look at the next segment. To be safe, we must be able to stop before
+    // the end of it, in case nothing else follows.
+    fn plan_exit_speed(&mut self) {
+        let exit_speed = match self.queue.front() {
+            Some(next) if self.continues_into(next) => {
+                let steps = (next.target - self.target).0.unsigned_abs() as f32;
+                let stoppable_speed = stoppable_speed(&next.params, steps);
+                min(min(self.stepgen.get_max_speed(), next.params.max_speed.0 as f32), stoppable_speed)
+            }
+            _ => 0.0,
+        };
+        self.stepgen.set_exit_speed(exit_speed);
+    }
+
+    // Invoked from the interrupt handler when the current segment is about to
+    // end, to blend it with the next one.
+    fn chain_next_segment(&mut self) {
+        match self.queue.front() {
+            Some(next) if self.continues_into(next) => {}
+            _ => return,
+        }
+        let segment = self.queue.pop_front().unwrap();
+
+        let steps = (segment.target - self.target).0.unsigned_abs();
+        self.target = segment.target;
+        self.apply_params(&segment.params);
+        self.stepgen.set_remaining_steps(steps);
+        self.plan_exit_speed();
     }
 
     pub fn set_origin(&mut self, origin_position: Steps) {
-        self.target = self.target + self.current_position - origin_position;
+        let offset = -origin_position - self.current_position;
+        self.target = self.target + offset;
+        for segment in self.queue.iter_mut() {
+            segment.target = segment.target + offset;
+        }
         self.current_position = -origin_position;
     }
 
+    // Decelerates to a stop, never past the current target. The target
+    // becomes where the plate comes to rest, so that segments enqueued in the
+    // meantime start from there.
     pub fn stop(&mut self) {
+        self.queue.clear();
+        self.stepgen.set_exit_speed(0.0);
+        let remaining_steps = self.stepgen.get_remaining_steps();
         self.stepgen.set_remaining_steps(
-            self.stepgen.num_steps_to_stop()
+            self.stepgen.num_steps_to_stop().min(remaining_steps)
         );
+        self.target = self.stop_position();
+    }
+
+    // Where the plate comes to rest, once the step scheduled on the timer and
+    // the remaining ones are done.
+    fn stop_position(&self) -> Steps {
+        if !self.moving {
+            return self.current_position;
+        }
+
+        let microsteps = self.driver.get_step_multiplier() + self.stepgen.get_remaining_steps();
+        // The microsteps that go into the backlash don't move the plate.
+        match self.driver.get_direction() {
+            Direction::Up => {
+                let taken_up = microsteps.min(self.backlash - self.backlash_offset);
+                self.current_position + Steps((microsteps - taken_up) as i32)
+            }
+            Direction::Down => {
+                let taken_up = microsteps.min(self.backlash_offset);
+                self.current_position - Steps((microsteps - taken_up) as i32)
+            }
+        }
     }
 
     pub fn hard_stop(&mut self) {
+        self.queue.clear();
+        self.stepgen.set_exit_speed(0.0);
         self.stepgen.set_remaining_steps(0);
         self.target = self.current_position;
 
-        self.step_timer.enable_update_interrupt(false);
-        self.drv8424.disable();
+        self.step_timer.enable_interrupt(false);
+        self.moving = false;
+        self.set_current_level(self.idle_current_level());
     }
 
     pub fn is_idle(&self) -> bool {
-        !self.drv8424.is_enabled()
+        !self.moving
+    }
+
+    // Once homed, the motor stays enabled to keep its position. Only drivers
+    // with current control (the Mono 4K's) can lower it to the hold current,
+    // the others (the Saturn's) stay at the run current.
+    fn idle_current_level(&self) -> CurrentLevel {
+        match (self.homed, D::HAS_CURRENT_CONTROL) {
+            (false, _) => CurrentLevel::Off,
+            (true, true) => CurrentLevel::Hold,
+            (true, false) => CurrentLevel::Run,
+        }
+    }
+
+    fn set_current_level(&mut self, level: CurrentLevel) {
+        if level == self.current_level {
+            return;
+        }
+
+        match level {
+            CurrentLevel::Off => self.driver.disable(),
+            CurrentLevel::Hold | CurrentLevel::Run => {
+                self.driver.set_current(level);
+                self.driver.enable();
+            }
+        }
+        self.current_level = level;
+    }
+
+    pub fn get_current_level(&self) -> CurrentLevel {
+        self.current_level
     }
 
     pub fn do_step<R>(&mut self, mut f: impl FnMut(&mut StepGenerator) -> R) -> R {
         let current_position = &mut self.current_position;
+        let backlash_offset = &mut self.backlash_offset;
+        let backlash = self.backlash;
         let stepgen = &mut self.stepgen;
 
-        self.drv8424.do_step(|drv| {
+        self.driver.do_step(|drv| {
+            // The microsteps that go into the backlash don't move the plate.
+            let microsteps = drv.get_step_multiplier();
             match drv.get_direction() {
-                Direction::Up   => current_position.0 += drv.step_multiplier as i32,
-                Direction::Down => current_position.0 -= drv.step_multiplier as i32,
+                Direction::Up => {
+                    let taken_up = microsteps.min(backlash - *backlash_offset);
+                    *backlash_offset += taken_up;
+                    current_position.0 += (microsteps - taken_up) as i32;
+                }
+                Direction::Down => {
+                    let taken_up = microsteps.min(*backlash_offset);
+                    *backlash_offset -= taken_up;
+                    current_position.0 -= (microsteps - taken_up) as i32;
+                }
             }
             f(stepgen)
         })
     }
+
+    pub fn driver(&self) -> &D {
+        &self.driver
+    }
+
+    pub fn step_timer(&self) -> &T {
+        &self.step_timer
+    }
+}
+
+// The highest speed from which we can stop within `steps`, with `params`.
+fn stoppable_speed(params: &MoveParams, steps: f32) -> f32 {
+    let d = params.deceleration.0 as f32;
+    match params.profile {
+        // steps = v^2/(2d)
+        Profile::Trapezoidal => sqrt(2.0 * d * steps),
+        // Stopping takes at most v/d + d/j, so steps <= v^2/(2d) + v*d/(2j).
+        // We solve for v.
+        Profile::SCurve { jerk } => {
+            let b = d*d/jerk;
+            (sqrt(b*b + 8.0*d*steps) - b) / 2.0
+        }
+    }
+}
+
+#[inline(always)]
+fn min(a: f32, b: f32) -> f32 {
+    if a <= b { a } else { b }
 }
@@ -1,4 +1,4 @@
-// SPDX-License-Identifier: GPL-3.mc-or-later
+// SPDX-License-Identifier: GPL-3.0-or-later
 
 use core::cell::Cell;
 
@@ -6,35 +6,45 @@ use embassy_util::channel::signal::Signal;
 
 use crate::util::SharedWithInterrupt;
 
-use super::{Steps, MotionControl, BottomSensor};
+use super::{
+    Steps, MotionControl, BottomSensor, Segment, MoveParams, MoveError,
+    Direction, StepperDriver, StepTimer, CurrentLevel,
+    BoardStepper, BoardStepTimer,
+};
 
-pub struct MotionControlAsync {
-    inner: SharedWithInterrupt<MotionControl>,
+// Number of events a task can wait on at once, see wait_any().
+pub const MAX_WAIT_EVENTS: usize = 4;
+
+pub struct MotionControlAsync<D: StepperDriver = BoardStepper, T: StepTimer = BoardStepTimer> {
+    inner: SharedWithInterrupt<MotionControl<D, T>>,
     pub bottom_sensor: BottomSensor,
 
-    signal_on_event: Cell<Option<Event>>,
-    signal: Signal<()>,
+    signal_on_events: Cell<[Option<Event>; MAX_WAIT_EVENTS]>,
+    // Carries the index of the event that was reached.
+    signal: Signal<usize>,
 }
 
-impl MotionControlAsync {
-    pub fn new(motion_control: SharedWithInterrupt<MotionControl>, bottom_sensor: BottomSensor) -> Self {
+impl<D: StepperDriver, T: StepTimer> MotionControlAsync<D, T> {
+    pub fn new(motion_control: SharedWithInterrupt<MotionControl<D, T>>, bottom_sensor: BottomSensor) -> Self {
         Self {
             inner: motion_control,
             bottom_sensor,
-            signal_on_event: Cell::new(None),
+            signal_on_events: Cell::new([None; MAX_WAIT_EVENTS]),
             signal: Signal::new(),
         }
     }
 
     pub fn on_interrupt(&mut self) {
-        let interrupt_fn = |mc: &mut MotionControl| {
+        let interrupt_fn = |mc: &mut MotionControl<D, T>| {
             mc.on_interrupt();
 
-            if let Some(event) = self.signal_on_event.get() {
-                if event.reached(&self) {
-                    self.signal_on_event.set(None);
-                    self.signal.signal(());
-                }
+            let events = self.signal_on_events.get();
+            let reached = events.iter().position(|event|
+                event.map(|e| e.reached(&self)).unwrap_or(false)
+            );
+            if let Some(index) = reached {
+                self.signal_on_events.set([None; MAX_WAIT_EVENTS]);
+                self.signal.signal(index);
             }
         };
 
@@ -42,20 +52,45 @@ impl MotionControlAsync {
     }
 
     pub async fn wait(&mut self, event: Event) {
-        let should_wait = self.inner.lock(|_| {
+        self.wait_any(&[event]).await;
+    }
+
+    // Waits until one of the events is reached, and returns its index in
+    // `events`. When several are reached at the same time, the first one wins.
+    // When the future is dropped before, e.g. with with_timeout(), the events
+    // are unregistered.
+    pub async fn wait_any(&mut self, events: &[Event]) -> usize {
+        assert!(!events.is_empty() && events.len() <= MAX_WAIT_EVENTS);
+
+        struct UnregisterOnDrop<'a, D: StepperDriver, T: StepTimer>(&'a MotionControlAsync<D, T>);
+        impl<'a, D: StepperDriver, T: StepTimer> Drop for UnregisterOnDrop<'a, D, T> {
+            fn drop(&mut self) {
+                let this = self.0;
+                this.inner.lock(|_| this.signal_on_events.set([None; MAX_WAIT_EVENTS]));
+            }
+        }
+
+        let reached = self.inner.lock(|_| {
             // We use the lock here because we need to atomically check for the
             // condition, and set the signal condition for the interrupt handler.
-            if event.reached(self) {
-                false
-            } else {
-                self.signal_on_event.set(Some(event));
+            let reached = events.iter().position(|event| event.reached(self));
+            if reached.is_none() {
+                let mut slots = [None; MAX_WAIT_EVENTS];
+                for (slot, event) in slots.iter_mut().zip(events) {
+                    *slot = Some(*event);
+                }
+                self.signal_on_events.set(slots);
                 self.signal.reset();
-                true
             }
+            reached
         });
 
-        if should_wait {
-            self.signal.wait().await;
+        match reached {
+            Some(index) => index,
+            None => {
+                let _guard = UnregisterOnDrop(self);
+                self.signal.wait().await
+            }
         }
     }
 
@@ -65,10 +100,56 @@ impl MotionControlAsync {
         self.inner.lock(|mc| mc.set_target_relative(steps))
     }
 
-    pub fn set_target(&self, target: Steps) {
+    pub fn set_target(&self, target: Steps) -> Result<(), MoveError> {
         self.inner.lock(|mc| mc.set_target(target))
     }
 
+    pub fn check_target(&self, target: Steps) -> Result<(), MoveError> {
+        self.inner.lock(|mc| mc.check_target(target))
+    }
+
+    pub fn set_homed(&self, homed: bool) {
+        self.inner.lock(|mc| mc.set_homed(homed))
+    }
+
+    pub fn is_homed(&self) -> bool {
+        self.inner.lock(|mc| mc.is_homed())
+    }
+
+    pub fn set_backlash(&self, backlash: Steps) {
+        self.inner.lock(|mc| mc.set_backlash(backlash))
+    }
+
+    pub fn get_backlash(&self) -> Steps {
+        self.inner.lock(|mc| mc.get_backlash())
+    }
+
+    pub fn set_limits(&self, min_position: Steps, max_position: Steps) {
+        self.inner.lock(|mc| mc.set_limits(min_position, max_position))
+    }
+
+    pub fn get_limits(&self) -> (Steps, Steps) {
+        self.inner.lock(|mc| mc.get_limits())
+    }
+
+    // The params and the target are applied together, the interrupt handler
+    // can't see one without the other.
+    pub fn move_to(&self, target: Steps, params: &MoveParams) -> Result<(), MoveError> {
+        self.inner.lock(|mc| mc.move_to(target, params))
+    }
+
+    pub fn move_relative(&self, steps: Steps, params: &MoveParams) {
+        self.inner.lock(|mc| mc.move_relative(steps, params))
+    }
+
+    pub fn move_until_stopped(&self, direction: Direction, params: &MoveParams) {
+        self.inner.lock(|mc| mc.move_until_stopped(direction, params))
+    }
+
+    pub fn enqueue(&self, segment: Segment) -> Result<(), MoveError> {
+        self.inner.lock(|mc| mc.enqueue(segment))
+    }
+
     pub fn stop(&mut self) {
         self.inner.lock(|mc| mc.stop())
     }
@@ -96,20 +177,94 @@ impl MotionControlAsync {
     pub fn is_idle(&self) -> bool {
         self.inner.lock(|mc| mc.is_idle())
     }
+
+    pub fn get_target(&self) -> Steps {
+        self.inner.lock(|mc| mc.get_target())
+    }
+
+    pub fn get_settings(&self) -> MotionSettings {
+        self.inner.lock(|mc| MotionSettings {
+            max_speed: mc.get_max_speed(),
+        })
+    }
+
+    pub fn set_settings(&self, settings: &MotionSettings) {
+        self.inner.lock(|mc| {
+            mc.set_max_speed(settings.max_speed);
+        })
+    }
+
+    // The current settings are restored when the guard is dropped.
+    pub fn settings_guard(&mut self) -> SettingsGuard<'_, D, T> {
+        let saved = self.get_settings();
+        SettingsGuard { mc: self, saved }
+    }
+
+    pub fn get_current_level(&self) -> CurrentLevel {
+        self.inner.lock(|mc| mc.get_current_level())
+    }
+}
+
+// Motion settings that tasks change for their own moves.
+#[derive(Clone, Copy, Debug)]
+pub struct MotionSettings {
+    pub max_speed: Steps,
+}
+
+// Restores the motion settings when dropped. When a task is cancelled, its
+// future is dropped, and so are the guards it holds.
+pub struct SettingsGuard<'a, D: StepperDriver = BoardStepper, T: StepTimer = BoardStepTimer> {
+    mc: &'a mut MotionControlAsync<D, T>,
+    saved: MotionSettings,
+}
+
+impl<'a, D: StepperDriver, T: StepTimer> core::ops::Deref for SettingsGuard<'a, D, T> {
+    type Target = MotionControlAsync<D, T>;
+    fn deref(&self) -> &Self::Target {
+        self.mc
+    }
+}
+
+impl<'a, D: StepperDriver, T: StepTimer> core::ops::DerefMut for SettingsGuard<'a, D, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.mc
+    }
+}
+
+impl<'a, D: StepperDriver, T: StepTimer> Drop for SettingsGuard<'a, D, T> {
+    fn drop(&mut self) {
+        // Still moving means the task was cancelled in the middle of a move.
+        // The restored settings would apply to that move, so we stop first.
+        if !self.mc.is_idle() {
+            self.mc.stop();
+        }
+        self.mc.set_settings(&self.saved);
+    }
 }
 
 #[derive(Clone, Copy)]
 pub enum Event {
     Idle,
     BottomSensor(bool),
+    // The plate is at, or past the position when going in the given direction.
+    PositionReached(Steps, Direction),
+    // The plate is within the given distance of where it will come to rest.
+    // Useful to prepare what comes next before the move ends.
+    NearTarget(Steps),
 }
 
 impl Event {
-    pub fn reached(&self, mc: &MotionControlAsync) -> bool {
+    pub fn reached<D: StepperDriver, T: StepTimer>(&self, mc: &MotionControlAsync<D, T>) -> bool {
         use Event::*;
         match self {
             Idle => mc.is_idle(),
             BottomSensor(value) => mc.bottom_sensor.active() == *value,
+            PositionReached(position, Direction::Up) => mc.get_current_position() >= *position,
+            PositionReached(position, Direction::Down) => mc.get_current_position() <= *position,
+            NearTarget(distance) => {
+                let remaining = mc.get_target() - mc.get_current_position();
+                remaining.0.unsigned_abs() <= distance.0.unsigned_abs()
+            }
         }
     }
 }
@@ -8,40 +8,127 @@ use crate::consts::zaxis::origin_calibration::*;
 use super::prelude::*;
 use crate::zaxis;
 
-pub async fn calibrate_origin(mc: &mut zaxis::MotionControlAsync, max_speed: Option<Steps>) {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HomingError {
+    // We went all the way down and the sensor never activated. It's most
+    // likely disconnected.
+    SensorNeverActivated,
+    // We went up and the sensor stayed active. It's stuck, or wired backwards.
+    SensorNeverReleased,
+    // The sensor released, but didn't activate again at the same place.
+    SensorUnreliable,
+    // The sensor says that the plate is at the bottom, but we expected it
+    // higher, or the other way around.
+    UnexpectedSensorState,
+    // The power went out while the plate was lifting, and it's too far from
+    // the sensor to find where it is without going down.
+    PositionUnknown,
+}
+
+pub async fn calibrate_origin(mc: &mut zaxis::MotionControlAsync, max_speed: Option<Steps>) -> Result<(), HomingError> {
     let max_speed = max_speed.unwrap_or(PHASE1_HOMING_SPEED_MM_PER_SEC.mm());
 
     mc.stop();
     mc.wait(zaxis::Event::Idle).await;
 
+    // Until we are done, the origin can't be trusted. This also lifts the
+    // soft limits, we use relative moves to go as far as needed.
+    mc.set_homed(false);
+
     // Phase 1: Go to the bottom of the zaxis.
     if !mc.bottom_sensor.active() {
         // We might be far away from the bottom, we want to go there quickly.
-        mc.set_max_speed(max_speed);
-        mc.set_target(Steps::MIN);
-        mc.wait(zaxis::Event::BottomSensor(true)).await;
+        move_until_sensor(mc, -PHASE1_MAX_DISTANCE_MM.mm(), max_speed, true).await
+            .map_err(|_| HomingError::SensorNeverActivated)?;
 
         mc.stop();
         mc.wait(zaxis::Event::Idle).await;
     }
 
+    find_origin_from_below(mc).await
+}
+
+// Phases 2 and 3 of calibrate_origin(). The bottom sensor must be active. The
+// plate goes down to where the sensor activates, never lower than where the
+// plate rested when it activated the sensor.
+async fn find_origin_from_below(mc: &mut zaxis::MotionControlAsync) -> Result<(), HomingError> {
     // Phase 2: Go a little above the sensor
-    mc.set_max_speed(PHASE2_HOMING_SPEED_MM_PER_SEC.mm());
-    mc.set_target(Steps::MAX);
-    mc.wait(zaxis::Event::BottomSensor(false)).await;
+    move_until_sensor(mc, PHASE2_MAX_DISTANCE_MM.mm(), PHASE2_HOMING_SPEED_MM_PER_SEC.mm(), false).await
+        .map_err(|_| HomingError::SensorNeverReleased)?;
     // Go slighly higher to avoid noisy sensor problems. I have not verified
     // that it was a problem, but who knows. We are willing to pay 0.5s of
     // traveling.
     mc.set_target_relative((PHASE3_HOMING_SPEED_MM_PER_SEC/2.0).mm());
     mc.wait(zaxis::Event::Idle).await;
+    // Otherwise phase 3 would see the sensor active right away.
+    if mc.bottom_sensor.active() {
+        return Err(HomingError::SensorNeverReleased);
+    }
 
     // Phase 3: Go slowly down until we hit the sensor
-    mc.set_max_speed(PHASE3_HOMING_SPEED_MM_PER_SEC.mm());
-    mc.set_target(Steps::MIN);
-    mc.wait(zaxis::Event::BottomSensor(true)).await;
+    move_until_sensor(mc, -PHASE3_MAX_DISTANCE_MM.mm(), PHASE3_HOMING_SPEED_MM_PER_SEC.mm(), true).await
+        .map_err(|_| HomingError::SensorUnreliable)?;
 
     // Set origin immediately and stop.
     mc.set_origin(-BOTTOM_SENSOR_POSITION_MM.mm());
+    mc.set_homed(true);
 
     mc.stop();
+
+    Ok(())
+}
+
+// After a power loss, the plate may be in the vat with a print stuck to it,
+// we can't go down to the bottom sensor. The lead screw doesn't turn without
+// power, so the plate is where the power went out: between `lowest`, where it
+// last rested coming from above, and `highest`. The two are the same unless
+// it was lifting.
+// Near the bottom, the sensor is active, and we find the origin going up
+// first, like calibrate_origin() does after phase 1. Otherwise, we take the
+// position as the origin, as long as we know it.
+pub async fn recover_origin(mc: &mut zaxis::MotionControlAsync, lowest: Steps, highest: Steps) -> Result<(), HomingError> {
+    mc.stop();
+    mc.wait(zaxis::Event::Idle).await;
+
+    if mc.bottom_sensor.active() {
+        // It rested above the sensor.
+        if lowest > BOTTOM_SENSOR_POSITION_MM.mm() {
+            return Err(HomingError::UnexpectedSensorState);
+        }
+        mc.set_homed(false);
+        return find_origin_from_below(mc).await;
+    }
+
+    // It rested below the sensor, but the sensor is not active.
+    if highest < BOTTOM_SENSOR_POSITION_MM.mm() {
+        return Err(HomingError::UnexpectedSensorState);
+    }
+
+    if highest > lowest {
+        return Err(HomingError::PositionUnknown);
+    }
+
+    mc.set_origin(-lowest);
+    mc.set_homed(true);
+
+    Ok(())
+}
+
+// Moves by `distance` at most, until the bottom sensor reaches `sensor_value`.
+// On failure, the motor is idle.
+async fn move_until_sensor(
+    mc: &mut zaxis::MotionControlAsync,
+    distance: Steps,
+    max_speed: Steps,
+    sensor_value: bool,
+) -> Result<(), ()> {
+    mc.move_relative(distance, &zaxis::MoveParams::new(max_speed));
+
+    // The move stops by itself at the end of the distance. If we get there
+    // first, we never saw the sensor.
+    let events = [zaxis::Event::BottomSensor(sensor_value), zaxis::Event::Idle];
+    match mc.wait_any(&events).await {
+        0 => Ok(()),
+        _ => Err(()),
+    }
 }
@@ -0,0 +1,220 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Adapts the slow lift speed to the force measured on the build plate while
+// peeling, following Jan Mrázek's work on force feedback. When the force gets
+// too high, the lift slows down right away to spare the print and the FEP, and
+// the next layers start slower. When layers come free early, the next ones
+// lift faster.
+
+use embassy_time::{Duration, with_timeout};
+
+use crate::consts::zaxis::peel_control::*;
+use crate::drivers::load_cell::ForceMonitor;
+
+use super::{
+    prelude::*,
+    LiftRetract, MoveError, Event, MotionControlAsync,
+    start_lift_and_retract,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct PeelConfig {
+    pub force_limit: f32, // g
+    pub release_force: f32, // g
+    pub min_speed: f32, // mm/s
+    pub max_speed: f32, // mm/s
+    pub decrease_factor: f32,
+    pub increase_factor: f32,
+    pub early_release_ratio: f32,
+}
+
+impl Default for PeelConfig {
+    fn default() -> Self {
+        Self {
+            force_limit: FORCE_LIMIT_G,
+            release_force: RELEASE_FORCE_G,
+            min_speed: MIN_SPEED_MM_PER_SEC,
+            max_speed: MAX_SPEED_MM_PER_SEC,
+            decrease_factor: SPEED_DECREASE_FACTOR,
+            increase_factor: SPEED_INCREASE_FACTOR,
+            early_release_ratio: EARLY_RELEASE_RATIO,
+        }
+    }
+}
+
+// What we observed during the slow lift of a layer.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PeelResult {
+    pub peak_force: f32, // g
+    // How far we lifted when the layer came free. None if we didn't see it.
+    pub release_distance: Option<Steps>,
+}
+
+pub struct PeelController {
+    pub config: PeelConfig,
+    // Slow lift speed of the next layer, in mm/s.
+    speed: f32,
+}
+
+impl PeelController {
+    pub fn new(config: PeelConfig, initial_speed: f32) -> Self {
+        let speed = initial_speed.clamp(config.min_speed, config.max_speed);
+        Self { config, speed }
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    // Picks the speed of the next layer.
+    pub fn update(&mut self, result: &PeelResult, lift_distance: Steps) {
+        let c = &self.config;
+        if result.peak_force > c.force_limit {
+            self.speed = (self.speed * c.decrease_factor).max(c.min_speed);
+        } else if let Some(release_distance) = result.release_distance {
+            if release_distance.as_mm() <= c.early_release_ratio * lift_distance.as_mm() {
+                self.speed = (self.speed * c.increase_factor).min(c.max_speed);
+            }
+        }
+    }
+}
+
+// Follows the force samples during the slow lift of a layer.
+pub struct PeelTracker {
+    config: PeelConfig,
+    start: Steps,
+    pub result: PeelResult,
+    slowed_down: bool,
+}
+
+impl PeelTracker {
+    pub fn new(config: PeelConfig, start: Steps) -> Self {
+        Self { config, start, result: PeelResult::default(), slowed_down: false }
+    }
+
+    // Returns true when the lift must slow down to the minimum speed, once.
+    pub fn on_sample(&mut self, force: f32, position: Steps) -> bool {
+        let c = &self.config;
+        let r = &mut self.result;
+
+        if force > r.peak_force {
+            r.peak_force = force;
+        }
+
+        if r.release_distance.is_none() && r.peak_force > c.release_force && force < c.release_force {
+            r.release_distance = Some(position - self.start);
+        }
+
+        let slow_down = force > c.force_limit && !self.slowed_down;
+        self.slowed_down |= slow_down;
+        slow_down
+    }
+}
+
+// Same as lift_and_retract(), with the slow lift speed chosen by the
+// controller. The force samples come from the load cell sampling task.
+pub async fn lift_and_retract_with_feedback(
+    mc: &mut MotionControlAsync,
+    lr: &LiftRetract,
+    target: Steps,
+    monitor: &ForceMonitor,
+    controller: &mut PeelController,
+) -> Result<PeelResult, MoveError> {
+    let mut lr = *lr;
+    lr.lift_slow.params.max_speed = controller.speed().mm();
+
+    mc.wait(Event::Idle).await;
+    let start = mc.get_current_position();
+    let peel_end = start_lift_and_retract(mc, &lr, target)?;
+
+    let timeout = Duration::from_millis(SAMPLE_TIMEOUT_MS);
+    let mut tracker = PeelTracker::new(controller.config, start);
+
+    while !mc.is_idle() && mc.get_current_position() < peel_end {
+        let force = match with_timeout(timeout, monitor.next_sample()).await {
+            Ok(force) => force,
+            Err(_) => {
+                warn!("No load cell samples, lifting without force feedback");
+                break;
+            }
+        };
+
+        if tracker.on_sample(force, mc.get_current_position()) {
+            // The suction force goes down with the speed.
+            mc.set_max_speed(controller.config.min_speed.mm());
+        }
+    }
+
+    mc.wait(Event::Idle).await;
+
+    let result = tracker.result;
+    controller.update(&result, lr.lift_slow.distance);
+    debug!("Peel: peak force {:.0}g, released after {:?}mm, next speed {:.2}mm/s",
+        result.peak_force, result.release_distance.map(|d| d.as_mm()), controller.speed());
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::load_cell::PeelModel;
+
+    const SAMPLE_PERIOD: f32 = 0.0125;
+    const LIFT_DISTANCE_MM: f32 = 5.0;
+
+    // Lifts through the model like lift_and_retract_with_feedback() does,
+    // moving at a constant speed between two samples.
+    fn peel(controller: &mut PeelController, stiffness: f32, damping: f32) -> PeelResult {
+        let start = 10.0f32.mm();
+        let mut model = PeelModel::new(start, 1.0f32.mm(), stiffness, damping, SAMPLE_PERIOD);
+        let mut tracker = PeelTracker::new(controller.config, start);
+
+        let mut speed = controller.speed();
+        let mut position_mm = start.as_mm();
+        while position_mm < start.as_mm() + LIFT_DISTANCE_MM {
+            position_mm += speed * SAMPLE_PERIOD;
+            let position = position_mm.mm();
+            if tracker.on_sample(model.force(position), position) {
+                speed = controller.config.min_speed;
+            }
+        }
+
+        controller.update(&tracker.result, LIFT_DISTANCE_MM.mm());
+        tracker.result
+    }
+
+    #[test]
+    fn slows_down_when_the_force_is_too_high() {
+        let mut controller = PeelController::new(PeelConfig::default(), MAX_SPEED_MM_PER_SEC);
+        // The suction dominates at full speed.
+        let result = peel(&mut controller, 500.0, 400.0);
+        assert!(result.peak_force > FORCE_LIMIT_G);
+        assert!(result.release_distance.is_some());
+        assert!(controller.speed() < MAX_SPEED_MM_PER_SEC);
+    }
+
+    #[test]
+    fn speeds_up_when_layers_come_free_early() {
+        let mut controller = PeelController::new(PeelConfig::default(), MIN_SPEED_MM_PER_SEC);
+        let result = peel(&mut controller, 500.0, 100.0);
+        assert!(result.peak_force < FORCE_LIMIT_G);
+        let release_distance = result.release_distance.unwrap().as_mm();
+        assert!((release_distance - 1.0).abs() < 0.05);
+        assert!(controller.speed() > MIN_SPEED_MM_PER_SEC);
+    }
+
+    #[test]
+    fn settles_around_the_force_limit() {
+        let mut controller = PeelController::new(PeelConfig::default(), MAX_SPEED_MM_PER_SEC);
+        for _ in 0..20 {
+            peel(&mut controller, 500.0, 400.0);
+        }
+        // The peak force is 500g + 400g/(mm/s) at the release, the speed
+        // goes back and forth around where it reaches the limit.
+        let limit_speed = (FORCE_LIMIT_G - 500.0) / 400.0;
+        let speed = controller.speed();
+        assert!(speed >= limit_speed * SPEED_DECREASE_FACTOR);
+        assert!(speed <= limit_speed * SPEED_INCREASE_FACTOR);
+    }
+}
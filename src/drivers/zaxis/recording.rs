@@ -0,0 +1,337 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// A stepper driver and a step timer that record what the motion control asks
+// of them, without any hardware. This makes it possible to run the motion
+// control on the host, and look at the steps it produces.
+
+use alloc::vec::Vec;
+
+use super::{Direction, StepperDriver, StepTimer, MotionControl};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordedStep {
+    pub direction: Direction,
+    pub multiplier: u32,
+}
+
+pub struct RecordingStepper {
+    direction: Direction,
+    step_multiplier: u32,
+    enabled: bool,
+    pub steps: Vec<RecordedStep>,
+}
+
+impl RecordingStepper {
+    pub fn new() -> Self {
+        Self { direction: Direction::Down, step_multiplier: 1, enabled: false, steps: Vec::new() }
+    }
+
+    /// Position in microsteps, relative to where the stepper was created.
+    pub fn position(&self) -> i32 {
+        self.steps.iter().map(|s| match s.direction {
+            Direction::Up => s.multiplier as i32,
+            Direction::Down => -(s.multiplier as i32),
+        }).sum()
+    }
+}
+
+impl StepperDriver for RecordingStepper {
+    const SUPPORTED_MULTIPLIERS: &'static [u32] = &[1, 2, 8, 16, 32, 64, 128, 256];
+
+    fn set_direction(&mut self, direction: Direction) {
+        self.direction = direction;
+    }
+
+    fn get_direction(&self) -> Direction {
+        self.direction
+    }
+
+    fn set_step_multiplier(&mut self, step_multiplier: u32) {
+        assert!(Self::SUPPORTED_MULTIPLIERS.contains(&step_multiplier),
+            "step multiplier {} is not supported", step_multiplier);
+        self.step_multiplier = step_multiplier;
+    }
+
+    fn get_step_multiplier(&self) -> u32 {
+        self.step_multiplier
+    }
+
+    fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn do_step<R>(&mut self, mut f: impl FnMut(&mut Self) -> R) -> R {
+        assert!(self.enabled, "stepping while the driver is disabled");
+        self.steps.push(RecordedStep { direction: self.direction, multiplier: self.step_multiplier });
+        f(self)
+    }
+}
+
+/// Records the delay between each interrupt, in timer ticks.
+pub struct RecordingStepTimer {
+    arr: u16,
+    interrupt_enabled: bool,
+    pub delays: Vec<u32>,
+}
+
+impl RecordingStepTimer {
+    pub fn new() -> Self {
+        Self { arr: 0, interrupt_enabled: false, delays: Vec::new() }
+    }
+
+    pub fn is_interrupt_enabled(&self) -> bool {
+        self.interrupt_enabled
+    }
+}
+
+impl StepTimer for RecordingStepTimer {
+    fn init(&mut self, _freq: u32) {}
+
+    fn set_auto_reload(&mut self, arr: u16) {
+        self.arr = arr;
+    }
+
+    fn reset_counter(&mut self) {}
+
+    fn enable_interrupt(&mut self, enable: bool) {
+        self.interrupt_enabled = enable;
+    }
+
+    fn clear_interrupt(&mut self) {
+        self.delays.push(self.arr as u32 + 1);
+    }
+}
+
+pub type RecordingMotionControl = MotionControl<RecordingStepper, RecordingStepTimer>;
+
+impl RecordingMotionControl {
+    pub fn new_recording() -> Self {
+        Self::new(RecordingStepper::new(), RecordingStepTimer::new())
+    }
+
+    /// Invokes the interrupt handler until the move completes, like the step
+    /// timer would.
+    pub fn run_until_idle(&mut self) {
+        while self.step_timer().is_interrupt_enabled() {
+            self.on_interrupt();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::consts::zaxis::{motion_control::*, stepper::STEP_TIMER_FREQ};
+    use super::super::{prelude::*, Direction, MoveParams, Segment, StepGenerator};
+    use super::*;
+
+    // Position of each step before it's done, relative to the start.
+    fn step_origins(steps: &[RecordedStep]) -> Vec<i32> {
+        let mut position = 0;
+        steps.iter().map(|s| {
+            let origin = position;
+            position += match s.direction {
+                Direction::Up => s.multiplier as i32,
+                Direction::Down => -(s.multiplier as i32),
+            };
+            origin
+        }).collect()
+    }
+
+    fn direction_changes(steps: &[RecordedStep]) -> usize {
+        steps.windows(2).filter(|s| s[0].direction != s[1].direction).count()
+    }
+
+    #[test]
+    fn relative_move_reaches_target() {
+        let mut mc = RecordingMotionControl::new_recording();
+        let distance = 10.0.mm();
+        mc.move_relative(distance, &MoveParams::default());
+        mc.run_until_idle();
+
+        assert_eq!(mc.get_current_position(), distance);
+        assert_eq!(mc.driver().position(), distance.0);
+        assert!(mc.driver().steps.iter().all(|s| s.direction == Direction::Up));
+        assert_eq!(direction_changes(&mc.driver().steps), 0);
+    }
+
+    #[test]
+    fn jogging_before_homing_is_bounded() {
+        let mut mc = RecordingMotionControl::new_recording();
+        mc.move_until_stopped(Direction::Down, &MoveParams::default());
+        mc.run_until_idle();
+
+        assert_eq!(mc.get_current_position(), -JOG_DISTANCE_MM.mm());
+    }
+
+    #[test]
+    fn profiles_are_per_move() {
+        let mut mc = RecordingMotionControl::new_recording();
+        mc.set_homed(true);
+        let speed = 5.0.mm();
+        // A gentle peel, chained into a quick travel.
+        mc.enqueue(Segment::new(1.0.mm(), MoveParams::gentle(speed))).unwrap();
+        mc.enqueue(Segment::new(6.0.mm(), MoveParams::new(speed))).unwrap();
+        mc.enqueue(Segment::new(2.0.mm(), MoveParams::gentle(speed))).unwrap();
+        mc.run_until_idle();
+
+        assert_eq!(mc.get_current_position(), 2.0.mm());
+        assert_eq!(mc.driver().position(), 2.0.mm().0);
+        assert_eq!(direction_changes(&mc.driver().steps), 1);
+    }
+
+    #[test]
+    fn multipliers_stay_on_the_microstep_grid() {
+        let mut mc = RecordingMotionControl::new_recording();
+        mc.move_relative(-20.0.mm(), &MoveParams::default());
+        mc.run_until_idle();
+
+        let steps = &mc.driver().steps;
+        // A fast move must go through the larger multipliers, or we can't
+        // keep up with the steps.
+        assert!(steps.iter().any(|s| s.multiplier > 1));
+        assert_eq!(steps.first().unwrap().multiplier, 1);
+
+        // The driver snaps to its microstepping grid, a step of m microsteps
+        // must start at a multiple of m.
+        for (step, origin) in steps.iter().zip(step_origins(steps)) {
+            assert_eq!(origin % step.multiplier as i32, 0,
+                "step of {} microsteps at {}", step.multiplier, origin);
+        }
+
+        // The multiplier goes through the supported ones, one at a time.
+        let supported = RecordingStepper::SUPPORTED_MULTIPLIERS;
+        let index = |m| supported.iter().position(|s| *s == m).unwrap();
+        for s in steps.windows(2) {
+            let (a, b) = (index(s[0].multiplier), index(s[1].multiplier));
+            assert!(a.abs_diff(b) <= 1, "{} -> {}", s[0].multiplier, s[1].multiplier);
+        }
+    }
+
+    #[test]
+    fn queued_segments_reverse_once() {
+        let mut mc = RecordingMotionControl::new_recording();
+        mc.set_homed(true);
+        let params = MoveParams::default();
+        mc.enqueue(Segment::new(5.0.mm(), params)).unwrap();
+        mc.enqueue(Segment::new(10.0.mm(), params)).unwrap();
+        mc.enqueue(Segment::new(4.0.mm(), params)).unwrap();
+        mc.run_until_idle();
+
+        assert_eq!(mc.get_current_position(), 4.0.mm());
+        assert_eq!(mc.driver().position(), 4.0.mm().0);
+        assert_eq!(direction_changes(&mc.driver().steps), 1);
+
+        // The first two segments blend into a single move up.
+        let up = mc.driver().steps.iter()
+            .take_while(|s| s.direction == Direction::Up)
+            .map(|s| s.multiplier as i32)
+            .sum::<i32>();
+        assert_eq!(up, 10.0.mm().0);
+    }
+
+    // Speeds in steps/s of the steps that start at or after `from`.
+    fn speeds_from(mc: &RecordingMotionControl, from: Steps) -> Vec<f32> {
+        let steps = &mc.driver().steps;
+        let delays = &mc.step_timer().delays;
+        step_origins(steps).into_iter().zip(steps).zip(delays)
+            .filter(|((origin, _), _)| *origin >= from.0)
+            .map(|((_, step), delay)| step.multiplier as f32 * STEP_TIMER_FREQ as f32 / *delay as f32)
+            .collect()
+    }
+
+    #[test]
+    fn chained_segments_use_their_own_speed() {
+        let fast = 10.0.mm();
+        let slow = 2.0.mm();
+
+        let mut mc = RecordingMotionControl::new_recording();
+        mc.set_homed(true);
+        mc.enqueue(Segment::new(5.0.mm(), MoveParams::new(fast))).unwrap();
+        mc.enqueue(Segment::new(10.0.mm(), MoveParams::new(slow))).unwrap();
+        mc.run_until_idle();
+        let max = speeds_from(&mc, 5.0.mm()).into_iter().fold(0.0, f32::max);
+        assert!(max <= slow.0 as f32 * 1.05, "{} > {}", max, slow.0);
+
+        let mut mc = RecordingMotionControl::new_recording();
+        mc.set_homed(true);
+        mc.enqueue(Segment::new(5.0.mm(), MoveParams::new(slow))).unwrap();
+        mc.enqueue(Segment::new(10.0.mm(), MoveParams::new(fast))).unwrap();
+        mc.run_until_idle();
+        let max = speeds_from(&mc, 5.0.mm()).into_iter().fold(0.0, f32::max);
+        assert!(max >= fast.0 as f32 * 0.95, "{} < {}", max, fast.0);
+    }
+
+    #[test]
+    fn set_origin_moves_queued_targets() {
+        let mut mc = RecordingMotionControl::new_recording();
+        mc.set_homed(true);
+        mc.enqueue(Segment::new(5.0.mm(), MoveParams::default())).unwrap();
+        mc.enqueue(Segment::new(8.0.mm(), MoveParams::default())).unwrap();
+        // The plate was at 3mm, not at 0mm.
+        mc.set_origin(-3.0.mm());
+        mc.run_until_idle();
+
+        assert_eq!(mc.get_current_position(), 11.0.mm());
+        assert_eq!(mc.driver().position(), 8.0.mm().0);
+    }
+
+    #[test]
+    fn enqueue_after_stop() {
+        let mut mc = RecordingMotionControl::new_recording();
+        mc.set_homed(true);
+        mc.move_to(20.0.mm(), &MoveParams::default()).unwrap();
+        while mc.get_current_position() < 5.0.mm() {
+            mc.on_interrupt();
+        }
+
+        mc.stop();
+        let rest = mc.get_target();
+        assert!(rest > 5.0.mm() && rest < 20.0.mm());
+
+        // Chained into the deceleration.
+        mc.enqueue(Segment::new(rest + 2.0.mm(), MoveParams::default())).unwrap();
+        mc.run_until_idle();
+        assert_eq!(mc.get_current_position(), rest + 2.0.mm());
+        assert_eq!(mc.driver().position(), (rest + 2.0.mm()).0);
+
+        // And the other way.
+        mc.move_to(20.0.mm(), &MoveParams::default()).unwrap();
+        while mc.get_current_position() < rest + 5.0.mm() {
+            mc.on_interrupt();
+        }
+        mc.stop();
+        let rest = mc.get_target();
+        mc.enqueue(Segment::new(rest - 3.0.mm(), MoveParams::default())).unwrap();
+        mc.run_until_idle();
+        assert_eq!(mc.get_current_position(), rest - 3.0.mm());
+        assert_eq!(mc.driver().position(), (rest - 3.0.mm()).0);
+    }
+
+    #[test]
+    fn fixed_microstepping_driver() {
+        // Like the step/dir driver of the saturn.
+        let mut stepgen = StepGenerator::new(
+            MAX_ACCELERATION.mm().0 as f32,
+            MAX_DECELERATION.mm().0 as f32,
+            MAX_SPEED.mm().0 as f32,
+            &[1],
+        );
+        stepgen.set_remaining_steps(10_000);
+        let mut steps = 0;
+        for (_delay, multiplier) in &mut stepgen {
+            assert_eq!(multiplier, 1);
+            steps += 1;
+        }
+        assert_eq!(steps, 10_000);
+    }
+}
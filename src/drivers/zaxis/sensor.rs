@@ -1,15 +1,22 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use embassy_stm32::gpio::{Input, Pull};
+
 use embassy_stm32::peripherals as p;
 
+// "zaxis bottom sensor: PC13" in misc/saturn_ports.txt
+#[cfg(feature="saturn")]
+type SensorPin = p::PC13;
+#[cfg(feature="mono4k")]
+type SensorPin = p::PB3;
+
 pub struct BottomSensor {
-    pin: Input<'static, p::PB3>,
+    pin: Input<'static, SensorPin>,
 }
 
 impl BottomSensor {
     pub fn new(
-        pin: p::PB3,
+        pin: SensorPin,
     ) -> Self {
         let pin = Input::new(pin, Pull::Up);
         Self { pin }
@@ -0,0 +1,210 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Virtual Z-axis hardware for the simulator.
+// The step timer runs on its own thread and invokes the motion control
+// interrupt handler, paced to the wall clock. The stepper integrates the steps
+// it receives into a position trace (a CSV file of time_us,position_steps),
+// and the bottom sensor is derived from the physical plate position.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::atomic::{AtomicBool, AtomicI32, AtomicU16, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::consts::{
+    simulator::*,
+    zaxis::stepper::STEP_TIMER_FREQ,
+};
+
+use super::{Direction, StepperDriver, StepTimer, CurrentLevel, prelude::*};
+
+// Physical position of the plate in steps. Unlike the position tracked by the
+// motion control, this one is not affected by set_origin().
+static PLATE_POSITION: AtomicI32 = AtomicI32::new(0);
+// Time as seen by the step timer, in timer ticks.
+static STEP_TIMER_TICKS: AtomicU64 = AtomicU64::new(0);
+static STEP_TIMER_ARR: AtomicU16 = AtomicU16::new(0);
+static STEP_TIMER_INTERRUPT_ENABLED: AtomicBool = AtomicBool::new(false);
+
+fn ticks_to_us(ticks: u64) -> u64 {
+    ticks * 1_000_000 / STEP_TIMER_FREQ as u64
+}
+
+pub fn plate_position() -> Steps {
+    Steps(PLATE_POSITION.load(Ordering::Relaxed))
+}
+
+pub struct VirtualStepTimer;
+
+impl StepTimer for VirtualStepTimer {
+    fn init(&mut self, freq: u32) {
+        // The interrupt thread counts time in STEP_TIMER_FREQ ticks.
+        assert!(freq == STEP_TIMER_FREQ);
+    }
+
+    fn set_auto_reload(&mut self, arr: u16) {
+        STEP_TIMER_ARR.store(arr, Ordering::Relaxed);
+    }
+
+    fn reset_counter(&mut self) {}
+
+    fn enable_interrupt(&mut self, enable: bool) {
+        STEP_TIMER_INTERRUPT_ENABLED.store(enable, Ordering::Release);
+    }
+
+    fn clear_interrupt(&mut self) {}
+}
+
+impl VirtualStepTimer {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Runs `on_interrupt` every arr+1 ticks while the update interrupt is
+    /// enabled, on a dedicated thread.
+    pub fn spawn_interrupt_thread(mut on_interrupt: impl FnMut() + Send + 'static) {
+        std::thread::spawn(move || {
+            let boot = Instant::now();
+            loop {
+                if !STEP_TIMER_INTERRUPT_ENABLED.load(Ordering::Acquire) {
+                    std::thread::sleep(Duration::from_millis(1));
+                    continue;
+                }
+
+                // When the timer was idle, its time source lagged behind. Catch up.
+                let now_ticks = boot.elapsed().as_micros() as u64 * STEP_TIMER_FREQ as u64 / 1_000_000;
+                STEP_TIMER_TICKS.fetch_max(now_ticks, Ordering::Relaxed);
+
+                let ticks = STEP_TIMER_ARR.load(Ordering::Relaxed) as u64 + 1;
+                let ticks = STEP_TIMER_TICKS.fetch_add(ticks, Ordering::Relaxed) + ticks;
+
+                // We can't sleep for a couple of microseconds, so we run ahead
+                // and sleep once we are more than 1ms ahead of the wall clock.
+                let ahead_us = ticks_to_us(ticks).saturating_sub(boot.elapsed().as_micros() as u64);
+                if ahead_us > 1000 {
+                    std::thread::sleep(Duration::from_micros(ahead_us));
+                }
+
+                on_interrupt();
+            }
+        });
+    }
+}
+
+/// Behaves like the Drv8424. Every step is recorded in the position trace.
+pub struct VirtualStepper {
+    direction: Direction,
+    enabled: bool,
+    current: CurrentLevel,
+    step_multiplier: u32,
+    trace: Option<BufWriter<File>>,
+}
+
+impl VirtualStepper {
+    pub fn new(trace_path: Option<&Path>) -> Self {
+        PLATE_POSITION.store(INITIAL_PLATE_POSITION_MM.mm().0, Ordering::Relaxed);
+
+        let trace = trace_path.map(|path| {
+            let mut trace = BufWriter::new(File::create(path)
+                .unwrap_or_else(|e| panic!("Failed to create {}: {}", path.display(), e)));
+            writeln!(trace, "time_us,position_steps").unwrap();
+            trace
+        });
+
+        Self { direction: Direction::Down, enabled: false, current: CurrentLevel::Run, step_multiplier: 0, trace }
+    }
+
+    // Called at the end of moves, a good time to make the trace visible on disk.
+    fn flush_trace(&mut self) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.flush().unwrap();
+        }
+    }
+}
+
+impl StepperDriver for VirtualStepper {
+    const HAS_CURRENT_CONTROL: bool = true;
+    // Same constraints as the DRV8424: 1/64 microstepping is not available.
+    const SUPPORTED_MULTIPLIERS: &'static [u32] = &[1, 2, 8, 16, 32, 64, 128, 256];
+
+    fn set_step_multiplier(&mut self, step_multiplier: u32) {
+        assert!(Self::SUPPORTED_MULTIPLIERS.contains(&step_multiplier),
+            "step multiplier {} is not supported", step_multiplier);
+        self.step_multiplier = step_multiplier;
+    }
+
+    fn get_step_multiplier(&self) -> u32 {
+        self.step_multiplier
+    }
+
+    fn set_direction(&mut self, direction: Direction) {
+        self.direction = direction;
+    }
+
+    fn get_direction(&self) -> Direction {
+        self.direction
+    }
+
+    fn do_step<R>(&mut self, mut f: impl FnMut(&mut Self) -> R) -> R {
+        if !self.enabled {
+            warn!("Stepping while the driver is disabled");
+        } else if self.current != CurrentLevel::Run {
+            warn!("Stepping with the {:?} current", self.current);
+        }
+
+        let delta = match self.direction {
+            Direction::Up => self.step_multiplier as i32,
+            Direction::Down => -(self.step_multiplier as i32),
+        };
+        let position = PLATE_POSITION.fetch_add(delta, Ordering::Relaxed) + delta;
+
+        if position < 0 {
+            warn!("The build plate crashed into the LCD panel");
+        } else if position > Z_AXIS_LENGTH_MM.mm().0 {
+            warn!("The build plate is past the top of the Z-axis");
+        }
+
+        if let Some(trace) = self.trace.as_mut() {
+            let time_us = ticks_to_us(STEP_TIMER_TICKS.load(Ordering::Relaxed));
+            writeln!(trace, "{},{}", time_us, position).unwrap();
+        }
+
+        f(self)
+    }
+
+    fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    fn disable(&mut self) {
+        self.enabled = false;
+        self.flush_trace();
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_current(&mut self, level: CurrentLevel) {
+        self.current = level;
+        if level == CurrentLevel::Hold {
+            self.flush_trace();
+        }
+    }
+}
+
+/// The bottom sensor activates when the plate is close to the LCD panel.
+pub struct BottomSensor;
+
+impl BottomSensor {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn active(&self) -> bool {
+        plate_position() <= BOTTOM_SENSOR_TRIGGER_MM.mm()
+    }
+}
@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The saturn stepper driver is only wired with STEP, DIR and ENABLE.
+// The microstepping resolution is fixed by hardware, and the motor current is
+// set with a potentiometer on the board.
+
+use embassy_stm32::gpio::{Output, Level, Speed};
+use embassy_stm32::peripherals as p;
+
+use crate::consts::zaxis::hardware::*;
+use crate::drivers::delay_ns_compensated;
+
+use super::{Direction, StepperDriver};
+
+// StepGenerator::next() takes at least ~110 cycles, which is what do_step() is
+// typically given. We only pad the STEP pulse with what's missing.
+const STEP_PULSE_WORK_CYCLES: u32 = 110;
+
+// The pins are from the port dump of the original firmware, see
+// misc/saturn_ports.txt. STEP and DIR are labeled there. ENABLE is not
+// verified: the dump only says "maybe in this group??" for PD12 (and PB1).
+pub struct StepDirDriver {
+    step: Output<'static, p::PA6>,
+    dir: Output<'static, p::PF15>,
+    enable: Output<'static, p::PD12>,
+}
+
+impl StepDirDriver {
+    pub fn new(
+        dir: p::PF15,
+        step: p::PA6,
+        enable: p::PD12, // Active low, unverified
+    ) -> Self {
+        let dir = Output::new(dir, Level::Low, Speed::Medium);
+        let step = Output::new(step, Level::Low, Speed::Medium);
+        // enable is active low. Start with the motor off.
+        let enable = Output::new(enable, Level::High, Speed::Medium);
+
+        Self { dir, step, enable }
+    }
+}
+
+impl StepperDriver for StepDirDriver {
+    const SUPPORTED_MULTIPLIERS: &'static [u32] = &[1];
+
+    fn set_step_multiplier(&mut self, step_multiplier: u32) {
+        assert!(step_multiplier == 1);
+    }
+
+    fn get_step_multiplier(&self) -> u32 {
+        1
+    }
+
+    fn set_direction(&mut self, direction: Direction) {
+        match direction {
+            Direction::Up => self.dir.set_high(),
+            Direction::Down => self.dir.set_low(),
+        }
+    }
+
+    fn get_direction(&self) -> Direction {
+        match self.dir.is_set_high() {
+            true  => Direction::Up,
+            false => Direction::Down,
+        }
+    }
+
+    fn do_step<R>(&mut self, mut f: impl FnMut(&mut Self) -> R) -> R {
+        self.step.set_high();
+        let ret = f(self);
+        // The CPU runs at 168Mhz, f() alone is not long enough.
+        delay_ns_compensated(STEP_PULSE_NS, STEP_PULSE_WORK_CYCLES);
+        self.step.set_low();
+        ret
+    }
+
+    fn enable(&mut self) {
+        self.enable.set_low();
+    }
+
+    fn disable(&mut self) {
+        self.step.set_low();
+        self.enable.set_high();
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enable.is_set_low()
+    }
+}
@@ -5,6 +5,11 @@
 // by Mihaylo Y. Stoychitch
 // See http://annals.fih.upt.ro/pdf-full/2013/ANNALS-2013-3-06.pdf
 // I prefer it to compared to https://www.embedded.com/generate-stepper-motor-speed-profiles-in-real-time/
+//
+// The linear speed profile changes the acceleration instantly, which shakes
+// the build plate. For gentle moves, like peeling a layer from the FEP, the
+// S-curve profile limits the jerk (the rate of change of the acceleration).
+// It is computed step by step in the velocity domain, see next_ci_s_curve().
 
 use crate::{consts::zaxis::{
     hardware::*,
@@ -12,15 +17,33 @@ use crate::{consts::zaxis::{
 }, drivers::delay_cycles};
 
 const TIMER_FREQ: f32 = STEP_TIMER_FREQ as f32;
-const MAX_STEP_MULTIPLIER: u32 = DRIVER_MICROSTEPS;
-// MIN_DELAY_VALUE is most of the time respected. It can be that for a single
-// step, the delay is going to be smaller, but immediately after, the step
-// multiplier will be corrected.
-// We could do a better implementation.
+// MIN_DELAY_VALUE is respected, except maybe for a single step, as the step
+// multiplier gets corrected.
 const MIN_DELAY_VALUE: f32 = STEP_TIMER_MIN_DELAY_VALUE;
+// We can only raise the step multiplier at positions aligned on the next
+// multiplier. Going from 2 to 8 (the DRV8424 has no 4) can take 3 steps. If
+// we waited for the delay to be under MIN_DELAY_VALUE, these steps would all
+// be too short. So we start trying a little before.
+const RAISE_MULTIPLIER_DELAY: f32 = 1.5*MIN_DELAY_VALUE;
+// Raising the multiplier by r multiplies the delay by r, so right after
+// raising, the delay is about RAISE_MULTIPLIER_DELAY*r. We lower it when the
+// delay goes above LOWER_MULTIPLIER_DELAY*r. The gap between the two keeps
+// us from flip flopping when the speed wobbles around the threshold.
+const LOWER_MULTIPLIER_DELAY: f32 = 2.0*MIN_DELAY_VALUE;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Profile {
+    // Linear speed, with instant changes of acceleration.
+    Trapezoidal,
+    // The jerk is in steps/s^3.
+    SCurve { jerk: f32 },
+}
 
-// The DRV8424 doesn't allow 1/64 microstepping because of the pin configuration
-const FORBIDDEN_MULTIPLIER: u32 = 4;
+impl Profile {
+    pub fn s_curve(jerk: super::Steps) -> Self {
+        Profile::SCurve { jerk: jerk.0 as f32 }
+    }
+}
 
 pub struct StepGenerator {
     ra: f32, // acceleration constant like in the paper
@@ -37,19 +60,42 @@ pub struct StepGenerator {
 
     remaining_steps: u32, // remaining steps. This is how we know that we need to move.
 
+    // When moves are chained, we don't come to a stop at the end of a move, we
+    // leave it at the exit speed instead. We treat this like a longer move that
+    // would stop exit_steps after the end of the current one.
+    exit_c: f32, // delay at the exit speed. Infinity when stopping.
+    exit_steps: f32, // steps needed to stop from the exit speed.
+
     // We want to change micro-stepping dynamically. This is the current step
-    // multiplier.  We start with 1, and can go up to MAX_STEP_MULTIPLIER=256,
-    // in increment of powers of two.
+    // multiplier.  We start with 1, and can go up to the largest multiplier
+    // that the stepper driver supports, going through the others.
     step_multiplier: u32,
+    multiplier_index: usize, // index of step_multiplier in `multipliers`
+    multipliers: &'static [u32],
+
+    profile: Profile,
+    // The following are only used with the S-curve profile. Units are
+    // expressed in timer ticks, like ra and rd.
+    j: f32, // jerk in steps/tick^3
+    sc0: f32, // initial delay, determined by the jerk
+    a: f32, // current acceleration in steps/tick^2
 }
 
 impl StepGenerator {
-    pub fn new(acceleration: f32, deceleration: f32, max_speed: f32) -> Self {
+    // `multipliers` are the step multipliers supported by the stepper driver.
+    // See StepperDriver::SUPPORTED_MULTIPLIERS.
+    pub fn new(acceleration: f32, deceleration: f32, max_speed: f32, multipliers: &'static [u32]) -> Self {
+        assert!(multipliers.first() == Some(&1));
+        assert!(multipliers.last().map_or(false, |m| *m <= DRIVER_MICROSTEPS));
+
         let mut self_ = Self {
             // We set all the values to 0.0, and set them with the set_* functions
             // to avoid duplicating code.
             ra: 0.0, rd: 0.0, c0: 0.0, ci: 0.0, target_c: 0.0, f2_over_2d: 0.0,
             n: 0, remaining_steps: 0, step_multiplier: 1,
+            exit_c: f32::INFINITY, exit_steps: 0.0,
+            multiplier_index: 0, multipliers,
+            profile: Profile::Trapezoidal, j: 0.0, sc0: 0.0, a: 0.0,
         };
 
         self_.set_acceleration(acceleration);
@@ -78,38 +124,185 @@ impl StepGenerator {
         TIMER_FREQ/self.target_c
     }
 
+    // The profile should be set while idle. Changing it during a move is
+    // safe, but it won't be smooth.
+    pub fn set_profile(&mut self, profile: Profile) {
+        if let Profile::SCurve { jerk } = profile {
+            assert!(jerk > 0.0);
+            let f = TIMER_FREQ;
+            self.j = jerk/(f*f*f);
+            // Starting from rest, the position is x(t) = j*t^3/6. The first
+            // step is done when x = 1.
+            self.sc0 = cbrt(6.0/self.j);
+            self.a = 0.0;
+        }
+        self.profile = profile;
+    }
+
+    pub fn get_profile(&self) -> Profile {
+        self.profile
+    }
+
     pub fn set_remaining_steps(&mut self, steps: u32) {
         self.remaining_steps = steps;
     }
 
+    pub fn get_remaining_steps(&self) -> u32 {
+        self.remaining_steps
+    }
+
+    // The speed (steps/s) at which we should be going when remaining_steps
+    // reaches 0. Use 0.0 to stop. Must be called after setting the deceleration
+    // and the profile.
+    pub fn set_exit_speed(&mut self, exit_speed: f32) {
+        if exit_speed <= 0.0 {
+            self.exit_c = f32::INFINITY;
+            self.exit_steps = 0.0;
+            return;
+        }
+
+        self.exit_c = TIMER_FREQ/exit_speed;
+        let exit_v = 1.0/self.exit_c;
+        self.exit_steps = match self.profile {
+            Profile::Trapezoidal => self.f2_over_2d * exit_v * exit_v,
+            Profile::SCurve { .. } => self.s_curve_stopping_distance(exit_v, 0.0),
+        };
+    }
+
     pub fn end_approaching(&self) -> bool {
         // The current speed is v=f/ci
         // it takes n = v**2/(2*deceleration) steps to come to a full stop.
         // We avoid using num_steps_to_stop(), because there's a division, and
         // that's 14 cycles. A multiplication is a single cycle.
-        self.remaining_steps as f32 * self.ci * self.ci <= self.f2_over_2d
+        (self.remaining_steps as f32 + self.exit_steps) * self.ci * self.ci <= self.f2_over_2d
     }
 
     pub fn num_steps_to_stop(&self) -> u32 {
-        let n = self.f2_over_2d / (self.ci * self.ci);
+        let n = match self.profile {
+            Profile::Trapezoidal => self.f2_over_2d / (self.ci * self.ci),
+            Profile::SCurve { .. } => self.s_curve_stopping_distance(1.0/self.ci, self.a),
+        };
         // We round a to avoid problems with end_approaching(). Note that if we
         // do an extra step while decelerating, it's not really a big deal.
         (n+0.5) as u32
     }
 
+    // Number of steps it takes to come to a full stop from the speed v
+    // (steps/tick) and the acceleration a, without exceeding the jerk and
+    // deceleration limits.
+    fn s_curve_stopping_distance(&self, v: f32, a: f32) -> f32 {
+        let j = self.j;
+        let d = -self.rd;
+
+        // If we are accelerating, we must first bring the acceleration down to
+        // 0, and the speed keeps increasing meanwhile.
+        let (v, ramp_distance) = if a > 0.0 {
+            let t = a/j;
+            let v_peak = v + a*t/2.0;
+            (v_peak, t*(v + v_peak)/2.0)
+        } else {
+            // When already decelerating, we overestimate the distance a
+            // little. That's fine, it keeps us braking.
+            (v, 0.0)
+        };
+
+        // The deceleration profile is symmetric, so the distance is
+        // v*t_stop/2. If v is small, we never reach the max deceleration.
+        let t_stop = if v*j >= d*d {
+            v/d + d/j
+        } else {
+            2.0*sqrt(v/j)
+        };
+
+        ramp_distance + v*t_stop/2.0
+    }
+
+    // Returns the next delay of the S-curve profile. Instead of computing
+    // delays directly like in the paper, we integrate the jerk into the
+    // acceleration, and the acceleration into the speed over the duration of
+    // the previous step.
+    // Note: ra, rd, and j are already scaled to timer ticks.
+    #[inline(always)]
+    fn next_ci_s_curve(&mut self, m: u32) -> f32 {
+        let j = self.j;
+        let a = self.a;
+        let v = 1.0/self.ci;
+        let target_v = 1.0/self.target_c;
+        // Duration of the previous step
+        let dt = self.ci * (m as f32);
+        // Speed variation while bringing the acceleration back to 0.
+        let dv_to_zero_acc = a*a/(2.0*j);
+
+        let exit_v = 1.0/self.exit_c;
+
+        let braking = self.remaining_steps as f32 + self.exit_steps
+            <= self.s_curve_stopping_distance(v, a);
+
+        let target_a = if braking {
+            // Ease into the stop, or the exit speed.
+            if a < 0.0 && v - exit_v <= dv_to_zero_acc { 0.0 } else { self.rd }
+        } else if v < target_v {
+            // Ease into the cruising speed.
+            if a > 0.0 && v + dv_to_zero_acc >= target_v { 0.0 } else { self.ra }
+        } else if v > target_v {
+            // The max_speed may have been lowered.
+            if a < 0.0 && v - dv_to_zero_acc <= target_v { 0.0 } else { self.rd }
+        } else {
+            0.0
+        };
+
+        let a = if a < target_a {
+            min(a + j*dt, target_a)
+        } else {
+            max(a - j*dt, target_a)
+        };
+
+        let mut next_v = v + a*dt;
+        self.a = a;
+
+        if !braking && (v < target_v) != (next_v < target_v) {
+            // We reached the cruising speed.
+            next_v = target_v;
+            self.a = 0.0;
+        }
+
+        if braking && next_v < exit_v {
+            next_v = exit_v;
+            self.a = 0.0;
+        }
+
+        // When braking, rounding errors may bring us to a stop a bit too early.
+        // We keep going at the speed of the first step.
+        let min_v = min(1.0/self.sc0, target_v);
+        if next_v < min_v {
+            next_v = min_v;
+            self.a = 0.0;
+        }
+
+        1.0/next_v
+    }
+
+    fn set_multiplier_index(&mut self, index: usize) {
+        self.multiplier_index = index;
+        self.step_multiplier = self.multipliers[index];
+    }
+
     pub fn adjust_step_multiplier(&mut self) {
         let m = self.step_multiplier;
+        let i = self.multiplier_index;
         let ci = self.ci;
         let effective_ci = ci*(m as f32);
 
-        let increase_rate = if m*2 == FORBIDDEN_MULTIPLIER { 4 } else { 2 };
-        let decrease_rate = if m/2 == FORBIDDEN_MULTIPLIER { 4 } else { 2 };
+        // The driver may not support all powers of two, so the rates are not
+        // necessarily 2.
+        let increase_rate = self.multipliers.get(i+1).map(|next| next/m).unwrap_or(1);
+        let decrease_rate = if i > 0 { m/self.multipliers[i-1] } else { 1 };
 
         if self.n == 0 {
-            self.step_multiplier = 1;
+            self.set_multiplier_index(0);
         } else if self.remaining_steps < self.step_multiplier {
-            self.step_multiplier /= decrease_rate;
-        } else if effective_ci < MIN_DELAY_VALUE && m != MAX_STEP_MULTIPLIER {
+            self.set_multiplier_index(i-1);
+        } else if effective_ci < RAISE_MULTIPLIER_DELAY && i+1 != self.multipliers.len() {
             // If the delay value becomes too small, we won't be able to keep up
             // sending pulses fast enough. We must rise the step multiplier.
             //  But we can only do so if the
@@ -121,13 +314,10 @@ impl StepGenerator {
             // a 1/256 microstep is so small.
             let next_multiplier = m*increase_rate;
             if (self.n+1) % next_multiplier == 0 {
-               self.step_multiplier = next_multiplier;
+               self.set_multiplier_index(i+1);
             }
-        } else if m != 1 && effective_ci > MIN_DELAY_VALUE*(decrease_rate as f32) + 0.01 {
-            // We add 0.01 to the condition to avoid flip flopping between two
-            // multipliers because of potential rounding errors. This condition
-            // hasn't been verified, I'm just being paranoid.
-            self.step_multiplier /= decrease_rate;
+        } else if m != 1 && effective_ci > LOWER_MULTIPLIER_DELAY*(decrease_rate as f32) {
+            self.set_multiplier_index(i-1);
         }
     }
 }
@@ -154,7 +344,16 @@ impl Iterator for StepGenerator {
             // See comment above for an explaination of this delay.
             delay_cycles(30);
             // self.step_multiplier is always 1 when starting, so this is correct.
-            self.c0
+            match self.profile {
+                Profile::Trapezoidal => self.c0,
+                Profile::SCurve { .. } => {
+                    self.a = 0.0;
+                    max(self.sc0, self.target_c)
+                }
+            }
+        } else if let Profile::SCurve { .. } = self.profile {
+            // This takes about 100 more cycles than the linear speed profile.
+            self.next_ci_s_curve(m)
         } else {
             // Returns the next ci after applying some acceleration
             // inline to use as little cycles as possible.
@@ -175,8 +374,9 @@ impl Iterator for StepGenerator {
             let m = m as f32;
             if self.end_approaching() {
                 // We must slow down to avoid missing the target while
-                // respecting the deceleration constraint
-                apply_acceleration(ci, m*self.rd)
+                // respecting the deceleration constraint. But no need to go
+                // slower than the exit speed.
+                min(apply_acceleration(ci, m*self.rd), self.exit_c)
             } else if self.target_c == ci {
                 // We are cruising.
                 ci
@@ -193,7 +393,10 @@ impl Iterator for StepGenerator {
 
         // These are the early/late step corrections as decribed in the paper.
         // Not sure how critical this is, but it's fairly cheap to implement.
-        let next_ci = {
+        let next_ci = if let Profile::SCurve { .. } = self.profile {
+            // The S-curve doesn't use the approximations of the paper.
+            next_ci
+        } else {
             // We'll most likely be at the maximum microstepping resolution for
             // these, which is to say, self.step_multiplier == 1. So we don't need
             // to worry about microstepping here.
@@ -206,7 +409,9 @@ impl Iterator for StepGenerator {
             ];
 
             match (self.n, self.remaining_steps) {
-                (n@1..=5, _) | (_, n@1..=5) => next_ci * CORRECTION[(n-1) as usize],
+                (n@1..=5, _) => next_ci * CORRECTION[(n-1) as usize],
+                // Not when we exit at speed into the next move.
+                (_, n@1..=5) if self.exit_steps == 0.0 => next_ci * CORRECTION[(n-1) as usize],
                 _ => next_ci
             }
         };
@@ -217,11 +422,9 @@ impl Iterator for StepGenerator {
 
         let effective_ci = next_ci * (m as f32);
 
-        // FIXME effective_ci may be smaller than MIN_DELAY_VALUE, just for one
-        // or two iterations. The delay will be in the right range, as the
-        // multiplier gets fixed. It's not great.
-        // There's not much harm done though.
-        // Having said that, there will be harm if effective_ci gets rounded to 0.
+        // effective_ci may be smaller than MIN_DELAY_VALUE for a single step.
+        // See zaxis::validation to check the profiles.
+        // There will be harm if effective_ci gets rounded to 0.
         assert!(effective_ci > 1.0);
 
         Some((effective_ci, m))
@@ -229,10 +432,25 @@ impl Iterator for StepGenerator {
 }
 
 #[inline(always)]
-fn sqrt(v: f32) -> f32 {
+pub(super) fn sqrt(v: f32) -> f32 {
     unsafe { core::intrinsics::sqrtf32(v) }
 }
 
+// Newton's method. Only used when configuring the profile, so it doesn't need
+// to be fast, but we don't want to pull libm for it.
+// x starts above the root, so it decreases monotonically.
+fn cbrt(v: f32) -> f32 {
+    let mut x = if v > 1.0 { v } else { 1.0 };
+    for _ in 0..100 {
+        let next_x = x - (x*x*x - v)/(3.0*x*x);
+        if x - next_x <= x*1e-6 {
+            return next_x;
+        }
+        x = next_x;
+    }
+    x
+}
+
 // Here we don't use the f32::min, because it's slower. It doesn't inline, and
 // does a bunch of extra stuff.
 #[inline(always)]
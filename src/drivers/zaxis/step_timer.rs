@@ -0,0 +1,37 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use embassy_stm32::{
+    peripherals as p,
+    rcc::low_level::RccPeripheral,
+    timer::low_level::{Basic16bitInstance, GeneralPurpose16bitInstance},
+};
+
+use super::StepTimer;
+
+// Any basic timer will do.
+impl StepTimer for p::TIM7 {
+    fn init(&mut self, freq: u32) {
+        Self::enable();
+        self.start();
+
+        let psc = (Self::frequency().0 / freq).checked_sub(1).unwrap();
+        let psc: u16 = psc.try_into().unwrap();
+        unsafe { Self::regs().psc().write(|w| w.set_psc(psc)) }
+    }
+
+    fn set_auto_reload(&mut self, arr: u16) {
+        unsafe { Self::regs().arr().write(|w| w.set_arr(arr)); }
+    }
+
+    fn reset_counter(&mut self) {
+        self.reset();
+    }
+
+    fn enable_interrupt(&mut self, enable: bool) {
+        self.enable_update_interrupt(enable);
+    }
+
+    fn clear_interrupt(&mut self) {
+        self.clear_update_interrupt();
+    }
+}
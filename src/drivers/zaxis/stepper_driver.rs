@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The motion control only needs a couple of things from the hardware: a
+// stepper motor driver, and a timer to schedule the steps. These traits are
+// what each printer (and the simulator) must provide.
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// How much current goes through the motor. Off is when the driver is
+/// disabled, the motor turns freely.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum CurrentLevel {
+    Off,
+    /// Enough to keep the plate in place when idle, without heating up the motor.
+    Hold,
+    Run,
+}
+
+pub trait StepperDriver {
+    /// The step multipliers that the driver can be configured with, in
+    /// increasing order, starting with 1. A step multiplier of m means that a
+    /// single step moves the motor by m microsteps of the finest resolution.
+    /// These must be powers of two, and can't exceed DRIVER_MICROSTEPS. A
+    /// driver with a fixed microstepping only supports 1.
+    const SUPPORTED_MULTIPLIERS: &'static [u32];
+
+    /// Whether set_current() changes the current. When it doesn't, the
+    /// motion control keeps the motor at the run current when idle instead of
+    /// asking for the hold current.
+    const HAS_CURRENT_CONTROL: bool = false;
+
+    /// Note: wait at least 200ns before stepping after changing the direction.
+    fn set_direction(&mut self, direction: Direction);
+    fn get_direction(&self) -> Direction;
+
+    /// Note: wait at least 200ns before stepping after changing the multiplier.
+    /// `step_multiplier` must be one of SUPPORTED_MULTIPLIERS.
+    fn set_step_multiplier(&mut self, step_multiplier: u32);
+    fn get_step_multiplier(&self) -> u32;
+
+    /// Note: wait 5us before the first step.
+    fn enable(&mut self);
+    fn disable(&mut self);
+    fn is_enabled(&self) -> bool;
+
+    /// Only called with Hold or Run, Off is done with disable(). Drivers
+    /// whose current is set in hardware (e.g. with a trimpot) always run at
+    /// the same current, and leave HAS_CURRENT_CONTROL to false.
+    fn set_current(&mut self, _level: CurrentLevel) {}
+
+    /// Steps the motor once. f() is invoked while the STEP signal is held, and
+    /// must take at least 1us. This gives the caller an opportunity to do useful
+    /// work instead of busy waiting.
+    fn do_step<R>(&mut self, f: impl FnMut(&mut Self) -> R) -> R;
+}
+
+/// The timer that generates the interrupts at which the motor is stepped.
+pub trait StepTimer {
+    /// Configures the timer to tick at `freq` Hz, and starts it.
+    fn init(&mut self, freq: u32);
+    /// The next interrupt fires `arr+1` ticks after the previous one.
+    fn set_auto_reload(&mut self, arr: u16);
+    /// Restarts counting from 0.
+    fn reset_counter(&mut self);
+    fn enable_interrupt(&mut self, enable: bool);
+    /// Must be called from the interrupt handler.
+    fn clear_interrupt(&mut self);
+}
@@ -0,0 +1,147 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Records what the step timer interrupt does, to understand a move after the
+// fact. The interrupt handler is the only writer, and overwrites the oldest
+// samples when the buffer is full. Recording is off by default, and costs a
+// single atomic load per interrupt in that case.
+// To look at a move: enable(), do the move, disable(), then dump the samples
+// to RTT with dump_to_log(), or to a file on the USB drive with dump_to_file().
+
+use core::cell::UnsafeCell;
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::consts::system::CLOCK_SPEED_MHZ;
+use crate::drivers::read_cycles;
+use crate::util::io::Write;
+
+use super::prelude::*;
+
+// Number of samples kept. 12 bytes each.
+pub const TELEMETRY_LEN: usize = 512;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    // CPU cycles, wraps around
+    pub cycles: u32,
+    // Position after the step
+    pub position: i32,
+    // Timer auto-reload value programmed for the next step. 0 when the move ended.
+    pub arr: u16,
+    pub multiplier: u16,
+}
+
+// The upper bit of the multiplier field holds the end approaching flag.
+// Multipliers are at most 256.
+const END_APPROACHING_FLAG: u16 = 0x8000;
+
+impl Sample {
+    const ZERO: Self = Self { cycles: 0, position: 0, arr: 0, multiplier: 0 };
+
+    pub fn multiplier(&self) -> u32 {
+        (self.multiplier & !END_APPROACHING_FLAG) as u32
+    }
+
+    pub fn end_approaching(&self) -> bool {
+        self.multiplier & END_APPROACHING_FLAG != 0
+    }
+}
+
+pub struct Telemetry {
+    enabled: AtomicBool,
+    // Total number of samples recorded, the next one goes at head % TELEMETRY_LEN.
+    head: AtomicU32,
+    samples: UnsafeCell<[Sample; TELEMETRY_LEN]>,
+}
+
+// The interrupt handler is the only writer, and readers must disable the
+// recording first.
+unsafe impl Sync for Telemetry {}
+
+pub static MOTION_TELEMETRY: Telemetry = Telemetry::new();
+
+impl Telemetry {
+    const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            head: AtomicU32::new(0),
+            samples: UnsafeCell::new([Sample::ZERO; TELEMETRY_LEN]),
+        }
+    }
+
+    // Discards the previous samples.
+    pub fn enable(&self) {
+        self.head.store(0, Ordering::Relaxed);
+        self.enabled.store(true, Ordering::Release);
+    }
+
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Release);
+    }
+
+    #[inline(always)]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    // Only called from the step timer interrupt handler.
+    #[inline(always)]
+    pub fn record(&self, position: Steps, arr: u16, multiplier: u32, end_approaching: bool) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut multiplier = multiplier as u16;
+        if end_approaching {
+            multiplier |= END_APPROACHING_FLAG;
+        }
+        let sample = Sample { cycles: read_cycles(), position: position.0, arr, multiplier };
+
+        let head = self.head.load(Ordering::Relaxed);
+        unsafe { (*self.samples.get())[head as usize % TELEMETRY_LEN] = sample };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+    }
+
+    // Invokes f() on the recorded samples, oldest first. Recording must be disabled.
+    pub fn for_each(&self, mut f: impl FnMut(&Sample)) {
+        assert!(!self.is_enabled(), "Telemetry must be disabled to be read");
+
+        let head = self.head.load(Ordering::Acquire) as usize;
+        let len = head.min(TELEMETRY_LEN);
+        let samples = unsafe { &*self.samples.get() };
+        for i in (head - len)..head {
+            f(&samples[i % TELEMETRY_LEN]);
+        }
+    }
+
+    // Calls f() with one CSV line per sample, header first. Time is in us
+    // since the first sample. The cycle counter wraps around after 25s at
+    // 168MHz, longer recordings have the wrong time.
+    fn for_each_csv_line(&self, mut f: impl FnMut(&str)) {
+        f("time_us,position,arr,multiplier,end_approaching\n");
+
+        let mut start_cycles = None;
+        self.for_each(|sample| {
+            let start = *start_cycles.get_or_insert(sample.cycles);
+            let time_us = sample.cycles.wrapping_sub(start) / CLOCK_SPEED_MHZ;
+
+            let mut line = heapless::String::<64>::new();
+            let _ = writeln!(line, "{},{},{},{},{}", time_us, sample.position,
+                sample.arr, sample.multiplier(), sample.end_approaching() as u8);
+            f(&line);
+        });
+    }
+
+    pub fn dump_to_log(&self) {
+        self.for_each_csv_line(|line| debug!("{}", line.trim_end()));
+    }
+
+    pub async fn dump_to_file<W: Write>(&self, file: &mut W) -> Result<(), W::Error> {
+        // We can't await in for_each(), so we build the whole file first.
+        // That's about 20KB.
+        let mut buf = alloc::vec::Vec::new();
+        self.for_each_csv_line(|line| buf.extend_from_slice(line.as_bytes()));
+        file.write(&buf).await
+    }
+}
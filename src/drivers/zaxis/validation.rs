@@ -0,0 +1,257 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Runs the step generator offline, the way the step timer interrupt drives it,
+// and checks the motion profiles it produces. From the delays and multipliers
+// of a move, we reconstruct the position, speed, and acceleration of the motor
+// over time.
+// The simulator runs these checks with `--validate-step-generator`, and so do
+// the host tests.
+
+use alloc::vec::Vec;
+
+use crate::consts::zaxis::{
+    stepper::*,
+    motion_control::*,
+    origin_calibration::PHASE3_HOMING_SPEED_MM_PER_SEC,
+};
+
+use super::{
+    prelude::*,
+    StepGenerator, Profile,
+    StepperDriver, BoardStepper,
+};
+
+const TIMER_FREQ: f32 = STEP_TIMER_FREQ as f32;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub max_speed: f32, // steps/s
+    pub acceleration: f32, // steps/s^2
+    pub deceleration: f32, // steps/s^2
+    // Relative tolerances. Speeds are rounded to timer ticks, and the linear
+    // speed profile uses the approximations of the paper.
+    pub speed_tolerance: f32,
+    pub acceleration_tolerance: f32,
+    // The linear speed profile applies the early/late step corrections of the
+    // paper to the last steps, which are off by up to ~20%. These steps are
+    // slow, so we don't check the acceleration over this duration at the end
+    // of a move, in timer ticks.
+    pub stop_window: f32,
+    // The acceleration is the speed difference over this duration, in timer
+    // ticks. Speeds are constant during a step, and steps have different
+    // durations when the step multiplier changes. Measuring over a single step
+    // would be too noisy.
+    pub acceleration_window: f32,
+}
+
+impl Limits {
+    pub fn new(max_speed: f32) -> Self {
+        Self {
+            max_speed,
+            acceleration: MAX_ACCELERATION.mm().0 as f32,
+            deceleration: MAX_DECELERATION.mm().0 as f32,
+            speed_tolerance: 0.01,
+            acceleration_tolerance: 0.05,
+            stop_window: 5e-3 * TIMER_FREQ,
+            acceleration_window: 5e-3 * TIMER_FREQ,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TraceStep {
+    // When the step happens, in timer ticks since the first step.
+    pub time: f32,
+    // Time since the previous step, in timer ticks.
+    pub delay: f32,
+    pub multiplier: u32,
+    // Position in steps before the step, relative to the start of the move.
+    pub position: u32,
+}
+
+impl TraceStep {
+    // Speed during the delay that precedes the step, in steps/s.
+    pub fn speed(&self) -> f32 {
+        (self.multiplier as f32) * TIMER_FREQ / self.delay
+    }
+
+    // Middle of the delay preceding the step. The speed is measured there.
+    fn mid_time(&self) -> f32 {
+        self.time - self.delay/2.0
+    }
+}
+
+pub struct Trace {
+    // The first step of the move happens before the step generator is invoked,
+    // it is not in this list.
+    pub steps: Vec<TraceStep>,
+}
+
+impl Trace {
+    // Same as what MotionControl::set_target() and MotionControl::on_interrupt() do.
+    pub fn record(stepgen: &mut StepGenerator, num_steps: u32) -> Self {
+        assert!(num_steps > 0);
+        stepgen.set_remaining_steps(num_steps-1);
+
+        let mut steps = Vec::new();
+        let mut time = 0.0;
+        let mut position = 1;
+        while let Some((delay, multiplier)) = stepgen.next() {
+            time += delay;
+            steps.push(TraceStep { time, delay, multiplier, position });
+            position += multiplier;
+        }
+
+        Self { steps }
+    }
+
+    // Including the first step.
+    pub fn num_steps(&self) -> u32 {
+        1 + self.steps.iter().map(|s| s.multiplier).sum::<u32>()
+    }
+
+    pub fn duration_sec(&self) -> f32 {
+        self.steps.last().map(|s| s.time).unwrap_or(0.0) / TIMER_FREQ
+    }
+
+    pub fn max_speed(&self) -> f32 {
+        self.steps.iter().map(|s| s.speed()).fold(0.0, f32::max)
+    }
+
+    // Returns the accelerations in steps/s^2, with the index of the step at
+    // which they are measured. Steps that are less than `window` ticks into
+    // the move are skipped.
+    pub fn accelerations(&self, window: f32) -> impl Iterator<Item=(usize, f32)> + '_ {
+        let mut from = 0;
+        self.steps.iter().enumerate().filter_map(move |(i, step)| {
+            // `from` is the most recent step at least `window` ticks earlier.
+            while from+1 < i && step.mid_time() - self.steps[from+1].mid_time() >= window {
+                from += 1;
+            }
+            let prev = &self.steps[from];
+            let dt = step.mid_time() - prev.mid_time();
+            if dt < window {
+                return None;
+            }
+            Some((i, (step.speed() - prev.speed()) * TIMER_FREQ / dt))
+        })
+    }
+
+    pub fn check(&self, num_steps: u32, limits: &Limits) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+
+        if self.num_steps() != num_steps {
+            violations.push(Violation::StepCount { expected: num_steps, actual: self.num_steps() });
+        }
+
+        let max_speed = limits.max_speed * (1.0 + limits.speed_tolerance);
+        let max_acceleration = limits.acceleration * (1.0 + limits.acceleration_tolerance);
+        let max_deceleration = limits.deceleration * (1.0 + limits.acceleration_tolerance);
+
+        let mut num_short_delays = 0;
+        for (i, step) in self.steps.iter().enumerate() {
+            if step.speed() > max_speed {
+                violations.push(Violation::MaxSpeed { step: i, speed: step.speed() });
+            }
+
+            if step.delay < STEP_TIMER_MIN_DELAY_VALUE {
+                num_short_delays += 1;
+                if num_short_delays == 2 {
+                    violations.push(Violation::MinDelay { step: i, delay: step.delay });
+                }
+            } else {
+                num_short_delays = 0;
+            }
+
+            // Otherwise, the driver snaps to the nearest microstep, and we lose steps.
+            if step.position % step.multiplier != 0 {
+                violations.push(Violation::UnalignedMultiplier {
+                    step: i, position: step.position, multiplier: step.multiplier,
+                });
+            }
+        }
+
+        let stop_time = self.steps.last().map(|s| s.time).unwrap_or(0.0) - limits.stop_window;
+        for (i, acceleration) in self.accelerations(limits.acceleration_window) {
+            if self.steps[i].time > stop_time {
+                break;
+            }
+            if acceleration > max_acceleration {
+                violations.push(Violation::Acceleration { step: i, acceleration });
+            } else if -acceleration > max_deceleration {
+                violations.push(Violation::Deceleration { step: i, deceleration: -acceleration });
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+// Step indexes refer to Trace::steps.
+#[derive(Debug)]
+pub enum Violation {
+    StepCount { expected: u32, actual: u32 },
+    MaxSpeed { step: usize, speed: f32 },
+    Acceleration { step: usize, acceleration: f32 },
+    Deceleration { step: usize, deceleration: f32 },
+    // The delay was below STEP_TIMER_MIN_DELAY_VALUE for more than one step.
+    MinDelay { step: usize, delay: f32 },
+    UnalignedMultiplier { step: usize, position: u32, multiplier: u32 },
+}
+
+// Checks the moves of the board we are building for, for both profiles, a
+// range of distances, and max speeds. Returns the number of failed moves.
+pub fn validate_step_generator() -> usize {
+    let profiles = [Profile::Trapezoidal, Profile::s_curve(MAX_JERK.mm())];
+    let max_speeds = [MAX_SPEED, MAX_SPEED/4.0, PHASE3_HOMING_SPEED_MM_PER_SEC];
+    let distances = [0.001, 0.01, 0.1, 1.0, 10.0, 100.0];
+
+    let mut num_failures = 0;
+
+    for profile in profiles {
+        for max_speed in max_speeds {
+            let mut stepgen = StepGenerator::new(
+                MAX_ACCELERATION.mm().0 as f32,
+                MAX_DECELERATION.mm().0 as f32,
+                max_speed.mm().0 as f32,
+                BoardStepper::SUPPORTED_MULTIPLIERS,
+            );
+            stepgen.set_profile(profile);
+            let limits = Limits::new(stepgen.get_max_speed());
+
+            for distance in distances {
+                let num_steps = distance.mm().0.max(1) as u32;
+                let trace = Trace::record(&mut stepgen, num_steps);
+
+                match trace.check(num_steps, &limits) {
+                    Ok(()) => {
+                        debug!("{:?} max_speed={}mm/s distance={}mm: ok, {:.3}s",
+                            profile, max_speed, distance, trace.duration_sec());
+                    }
+                    Err(violations) => {
+                        num_failures += 1;
+                        warn!("{:?} max_speed={}mm/s distance={}mm: {} violations, first ones:",
+                            profile, max_speed, distance, violations.len());
+                        for violation in violations.iter().take(5) {
+                            warn!("  {:?}", violation);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    num_failures
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn step_generator_profiles() {
+        assert_eq!(super::validate_step_generator(), 0);
+    }
+}
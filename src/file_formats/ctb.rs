@@ -189,6 +189,37 @@ impl Header {
             _ => Err(()),
         }
     }
+
+    pub fn has_layer_ex(&self) -> bool {
+        self.version >= 3
+    }
+
+    // Area of the build plate covered by one pixel.
+    pub fn pixel_area_mm2(&self) -> f32 {
+        let (x, y) = (self.bed_size_x, self.bed_size_y);
+        (x / self.resolution_x as f32) * (y / self.resolution_y as f32)
+    }
+}
+
+// At header.print_settings_offset
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug)]
+pub struct PrintSettings {
+    pub bottom_lift_height_mm: f32,
+    pub bottom_lift_speed_mm_per_min: f32,
+    pub normal_lift_height_mm: f32,
+    pub normal_lift_speed_mm_per_min: f32,
+    pub normal_retract_speed_mm_per_min: f32,
+    pub volume_ml: f32,
+    pub weight_g: f32,
+    pub cost_dollars: f32,
+    pub bottom_light_off_delay_sec: f32,
+    pub normal_light_off_delay_sec: f32,
+    pub bottom_layer_count: u32,
+    pub unknown1: u32,
+    pub unknown2: u32,
+    pub unknown3: u32,
+    pub unknown4: u32,
 }
 
 #[repr(C, packed)]
@@ -205,6 +236,35 @@ pub struct Layer {
     pub unknown4: u32,
 }
 
+// Files of version 3 and above repeat the layer definition right before the
+// image data, with per-layer settings.
+// At layer.image_offset - size_of::<LayerEx>()
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug)]
+pub struct LayerEx {
+    pub layer: Layer,
+    pub total_size: u32,
+    pub lift_height_mm: f32,
+    pub lift_speed_mm_per_min: f32,
+    pub lift_height2_mm: f32,
+    pub lift_speed2_mm_per_min: f32,
+    pub retract_speed_mm_per_min: f32,
+    pub retract_height2_mm: f32,
+    pub retract_speed2_mm_per_min: f32,
+    pub rest_time_before_lift_sec: f32,
+    pub rest_time_after_lift_sec: f32,
+    pub rest_time_after_retract_sec: f32,
+    pub light_pwm: f32, // 0 to 255
+}
+
+impl Layer {
+    // The LayerEx sits right before the image. None when the image offset is
+    // too small for that, the file is corrupted.
+    pub fn layer_ex_offset(&self) -> Option<u32> {
+        self.image_offset.checked_sub(core::mem::size_of::<LayerEx>() as u32)
+    }
+}
+
 #[inline(always)]
 pub fn div_round_up(v: usize, denom: usize) -> usize {
     (v + denom - 1)/denom
@@ -11,7 +11,10 @@ impl log::Log for Logger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
+            #[cfg(not(feature="simulator"))]
             rtt_target::rprintln!("{}", record.args());
+            #[cfg(feature="simulator")]
+            std::println!("{}", record.args());
         }
     }
     fn flush(&self) {}
@@ -20,6 +23,7 @@ impl log::Log for Logger {
 static LOGGER: Logger = Logger;
 
 pub fn init_logging() {
+    #[cfg(not(feature="simulator"))]
     rtt_target::rtt_init_print!(NoBlockSkip, 4096);
     //rtt_target::rtt_init_print!(BlockIfFull, 4096);
 
@@ -1,8 +1,8 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-#![no_std]
-#![no_main]
-#![feature(alloc_error_handler)]
+#![cfg_attr(not(feature="simulator"), no_std)]
+#![cfg_attr(not(feature="simulator"), no_main)]
+#![cfg_attr(not(feature="simulator"), feature(alloc_error_handler))]
 #![feature(type_alias_impl_trait)]
 #![feature(maybe_uninit_as_bytes)]
 #![feature(maybe_uninit_uninit_array)]
@@ -27,6 +27,8 @@ mod ui;
 mod util;
 mod file_formats;
 mod logging;
+mod settings;
+mod print;
 
 use core::cell::RefCell;
 use core::mem::MaybeUninit;
@@ -35,6 +37,7 @@ use lvgl::core::{Lvgl, TouchPad, Display, ObjExt};
 
 use embassy_time::{Duration, Timer};
 use embassy_util::{Forever, blocking_mutex::CriticalSectionMutex as Mutex};
+#[cfg(not(feature="simulator"))]
 use embassy_stm32::{
     Config,
     interrupt,
@@ -49,6 +52,7 @@ use drivers::{
     display::Display as RawDisplay,
     zaxis,
     usb::UsbHost, lcd::Lcd,
+    uv_light::UvExposure,
 };
 
 use crate::util::TaskRunner;
@@ -57,13 +61,31 @@ use crate::util::TaskRunner;
 pub static Z_AXIS: Forever<zaxis::MotionControlAsync> = Forever::new();
 static USB_HOST: Forever<UsbHost> = Forever::new();
 pub static TASK_RUNNER: Forever<TaskRunner<ui::Task>> = Forever::new();
+pub static PRINT_TASK_RUNNER: Forever<TaskRunner<print::PrintTask>> = Forever::new();
 static LCD: Forever<Lcd> = Forever::new();
+static UV_EXPOSURE: Forever<UvExposure> = Forever::new();
+#[cfg(not(feature="mono4k"))]
+static CHECKPOINT_LOG: Forever<print::CheckpointLog> = Forever::new();
 
+#[cfg(not(feature="simulator"))]
 #[interrupt]
 fn TIM7() {
     unsafe { Z_AXIS.steal().on_interrupt() }
 }
 
+#[cfg(feature="saturn")]
+#[interrupt]
+fn TIM6_DAC() {
+    unsafe { UV_EXPOSURE.steal().on_interrupt() }
+}
+
+#[cfg(feature="mono4k")]
+#[interrupt]
+fn TIM6() {
+    unsafe { UV_EXPOSURE.steal().on_interrupt() }
+}
+
+#[cfg(not(feature="simulator"))]
 #[interrupt]
 fn OTG_FS() {
     unsafe { USB_HOST.steal().on_interrupt() }
@@ -98,6 +120,63 @@ mod medium_priority_tasks {
         let task_runner = unsafe { TASK_RUNNER.steal() };
         task_runner.main_loop_task(z_axis).await;
     }
+
+    #[embassy_executor::task]
+    pub async fn print_task(settings: settings::Settings) {
+        // The mono4k has no storage for the checkpoints.
+        #[cfg(not(feature="mono4k"))]
+        let checkpoints = Some(unsafe { CHECKPOINT_LOG.steal() });
+        #[cfg(feature="mono4k")]
+        let checkpoints = None;
+
+        let mut ctx = print::PrintContext {
+            zaxis: unsafe { Z_AXIS.steal() },
+            lcd: unsafe { LCD.steal() },
+            usb_host: unsafe { USB_HOST.steal() },
+            uv: unsafe { UV_EXPOSURE.steal() },
+            checkpoints,
+            settings,
+        };
+        let task_runner = unsafe { PRINT_TASK_RUNNER.steal() };
+        task_runner.main_loop_task(&mut ctx).await;
+    }
+
+    #[cfg(all(feature="load_cell", not(feature="simulator")))]
+    #[embassy_executor::task]
+    pub async fn load_cell_task(mut sensor: drivers::load_cell::BoardLoadCell) {
+        drivers::load_cell::run(&mut sensor, &drivers::load_cell::FORCE_MONITOR).await
+    }
+
+    // The plate peels off a virtual layer, see PeelModel.
+    #[cfg(all(feature="load_cell", feature="simulator"))]
+    #[embassy_executor::task]
+    pub async fn load_cell_task() {
+        use consts::load_cell::*;
+        use drivers::load_cell::{self, PeelModel, MockForceSensor};
+        use zaxis::prelude::*;
+
+        let z_axis = unsafe { Z_AXIS.steal() };
+        let mut model = PeelModel::new(
+            z_axis.get_current_position(),
+            PEEL_RELEASE_DISTANCE_MM.mm(),
+            PEEL_STIFFNESS_G_PER_MM,
+            PEEL_DAMPING_G_PER_MM_PER_SEC,
+            SAMPLE_PERIOD_MS as f32 / 1000.0,
+        );
+        let mut sensor = MockForceSensor::new(
+            move || model.force(z_axis.get_current_position()),
+            Duration::from_millis(SAMPLE_PERIOD_MS),
+            1.0 / GRAMS_PER_COUNT,
+        );
+        load_cell::run(&mut sensor, &load_cell::FORCE_MONITOR).await
+    }
+}
+
+#[cfg(not(feature="mono4k"))]
+fn open_checkpoint_log(storage: &'static mut dyn print::CheckpointStorage) {
+    let (log, checkpoint) = print::CheckpointLog::open(storage);
+    CHECKPOINT_LOG.put(log);
+    print::RESUMABLE_PRINT.lock(|c| c.set(checkpoint));
 }
 
 mod low_priority_tasks {
@@ -107,6 +186,7 @@ mod low_priority_tasks {
 
 }
 
+#[cfg(not(feature="simulator"))]
 #[cortex_m_rt::entry]
 fn main() -> ! {
     logging::init_logging();
@@ -117,7 +197,6 @@ fn main() -> ! {
         Machine::new(cp, p)
     };
 
-    #[cfg(feature="mono4k")]
     Z_AXIS.put(zaxis::MotionControlAsync::new(
         crate::util::SharedWithInterrupt::new(machine.stepper),
         machine.z_bottom_sensor,
@@ -133,7 +212,16 @@ fn main() -> ! {
     }
     //debug!("FPGA version: {:x}", lcd.get_version());
 
+    UV_EXPOSURE.put(machine.uv);
+
+    #[cfg(feature="saturn")]
+    {
+        static EXT_FLASH: Forever<drivers::ext_flash::ExtFlash> = Forever::new();
+        open_checkpoint_log(EXT_FLASH.put(machine.ext_flash));
+    }
+
     TASK_RUNNER.put(Default::default());
+    PRINT_TASK_RUNNER.put(Default::default());
 
     // Maximum priority (P4)
     {
@@ -144,6 +232,16 @@ fn main() -> ! {
         irq.enable();
     }
 
+    {
+        // Also maximum priority, the UV light must go off on time.
+        #[cfg(feature="saturn")]
+        let irq: interrupt::TIM6_DAC = unsafe { ::core::mem::transmute(()) };
+        #[cfg(feature="mono4k")]
+        let irq: interrupt::TIM6 = unsafe { ::core::mem::transmute(()) };
+        irq.set_priority(interrupt::Priority::P4);
+        irq.enable();
+    }
+
     // High priority (P5)
     {
         // This is quick. It's to service the I/O between main memory and the USB IP-core FIFOs.
@@ -169,6 +267,9 @@ fn main() -> ! {
         executor.must_spawn(ui::lvgl_tick_task(lvgl.ticks()));
         executor.must_spawn(medium_priority_tasks::main_task());
         executor.must_spawn(medium_priority_tasks::usb_stack());
+        executor.must_spawn(medium_priority_tasks::print_task(machine.settings));
+        #[cfg(feature="load_cell")]
+        executor.must_spawn(medium_priority_tasks::load_cell_task(machine.load_cell));
 
         //spawner.spawn(medium_priority_tasks::lcd_task(lcd_receiver)).unwrap();
     }
@@ -180,6 +281,88 @@ fn main() -> ! {
     }
 }
 
+// The simulator runs the same tasks as the firmware, but on host threads
+// instead of interrupt priorities.
+#[cfg(feature="simulator")]
+fn main() {
+    logging::init_logging();
+
+    let config = drivers::simulator::Config::from_args();
+
+    if config.validate_step_generator {
+        let num_failures = zaxis::validation::validate_step_generator();
+        std::process::exit(if num_failures == 0 { 0 } else { 1 });
+    }
+
+    let machine = Machine::new(&config);
+
+    Z_AXIS.put(zaxis::MotionControlAsync::new(
+        crate::util::SharedWithInterrupt::new(machine.stepper),
+        machine.z_bottom_sensor,
+    ));
+
+    let (lvgl, display) = ui::lvgl_init(machine.display);
+
+    USB_HOST.put(machine.usb_host);
+
+    {
+        let lcd = LCD.put(machine.lcd);
+        lcd.init();
+    }
+
+    UV_EXPOSURE.put(machine.uv);
+
+    {
+        static CHECKPOINT_STORAGE: Forever<print::FileCheckpointStorage> = Forever::new();
+        let path = config.output_dir.join("checkpoints.bin");
+        open_checkpoint_log(CHECKPOINT_STORAGE.put(print::FileCheckpointStorage(path)));
+    }
+
+    TASK_RUNNER.put(Default::default());
+    PRINT_TASK_RUNNER.put(Default::default());
+
+    if let Some(file_name) = &config.print_file {
+        let file_name = print::FileName::new(file_name)
+            .unwrap_or_else(|_| panic!("Invalid file name: {}", file_name));
+        unsafe { PRINT_TASK_RUNNER.steal() }.enqueue_task(print::PrintTask::Print { file_name, start_layer: config.print_start_layer }).unwrap();
+    }
+
+    // Stands in for the TIM7 interrupt
+    zaxis::simulator::VirtualStepTimer::spawn_interrupt_thread(|| {
+        unsafe { Z_AXIS.steal().on_interrupt() }
+    });
+
+    // Stands in for the TIM6 interrupt
+    drivers::uv_light::simulator::VirtualExposureTimer::spawn_interrupt_thread(|| {
+        unsafe { UV_EXPOSURE.steal().on_interrupt() }
+    });
+
+    // Stands in for the medium priority executor
+    {
+        let touch_screen = machine.touch_screen;
+        let lvgl_ticks = lvgl.ticks();
+        let settings = machine.settings;
+        std::thread::spawn(move || {
+            // Executors must live forever
+            let executor = alloc::boxed::Box::leak(alloc::boxed::Box::new(
+                embassy_executor::Executor::new()
+            ));
+            executor.run(|spawner| {
+                spawner.must_spawn(ui::touch_screen_task(touch_screen));
+                spawner.must_spawn(ui::lvgl_tick_task(lvgl_ticks));
+                spawner.must_spawn(medium_priority_tasks::main_task());
+                spawner.must_spawn(medium_priority_tasks::usb_stack());
+                spawner.must_spawn(medium_priority_tasks::print_task(settings));
+                #[cfg(feature="load_cell")]
+                spawner.must_spawn(medium_priority_tasks::load_cell_task());
+            })
+        });
+    }
+
+    // Idle task
+    ui::idle_task(lvgl, display)
+}
+
 /*
             let mut file = fs.open("TEST_P~1.CTB", Mode::ReadOnly).await?;
 
@@ -0,0 +1,295 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Print checkpoints, to resume after a power loss. After each layer, the print
+// records which layer it completed, and where the plate is. It also records
+// when the plate starts lifting, as its position is not exactly known until
+// it rests again.
+// Flash sectors wear out after about 100k erases. Instead of erasing a sector
+// for every layer, records are appended to a log that spans a few sectors. A
+// sector is only erased when the log wraps around to it, and the most recent
+// record is the valid one with the highest sequence number.
+
+use core::cell::Cell;
+use core::mem::{size_of, MaybeUninit};
+
+use embassy_util::blocking_mutex::CriticalSectionMutex as Mutex;
+
+use crate::consts::print::*;
+use crate::drivers::zaxis::prelude::*;
+use crate::settings::fnv1a;
+
+use super::FileName;
+
+const MAGIC: u32 = 0xc4ec_4901;
+const RECORD_SIZE: u32 = 64;
+const RECORDS_PER_SECTOR: u32 = CHECKPOINT_SECTOR_SIZE / RECORD_SIZE;
+const NUM_RECORDS: u32 = RECORDS_PER_SECTOR * CHECKPOINT_NUM_SECTORS;
+
+// Where the print stands, once a layer is complete.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Checkpoint {
+    file_name: [u8; MAX_FILE_NAME_LEN],
+    file_name_len: u32,
+    // The last layer that was exposed.
+    pub layer_index: u32,
+    // Where the plate rests, at the position of the next layer. While
+    // lifting, where it rested before the lift.
+    pub position_um: i32,
+    // While lifting, the plate is anywhere between position_um and this. 0 in
+    // older records, which were only written at rest.
+    pub lift_top_um: i32,
+}
+
+impl Checkpoint {
+    pub fn new(file_name: &FileName, layer_index: u32, position: Distance) -> Self {
+        let mut buf = [0; MAX_FILE_NAME_LEN];
+        let name = file_name.as_str().as_bytes();
+        buf[..name.len()].copy_from_slice(name);
+        Self {
+            file_name: buf,
+            file_name_len: name.len() as u32,
+            layer_index,
+            position_um: position.um(),
+            lift_top_um: position.um(),
+        }
+    }
+
+    // The plate is about to lift from `position` up to `top`.
+    pub fn lifting(file_name: &FileName, layer_index: u32, position: Distance, top: Distance) -> Self {
+        let mut checkpoint = Self::new(file_name, layer_index, position);
+        checkpoint.lift_top_um = top.um();
+        checkpoint
+    }
+
+    // None if the name is corrupted.
+    pub fn file_name(&self) -> Option<FileName> {
+        let len = self.file_name_len as usize;
+        let name = self.file_name.get(..len)?;
+        FileName::new(core::str::from_utf8(name).ok()?).ok()
+    }
+
+    pub fn position(&self) -> Distance {
+        Distance::from_um(self.position_um)
+    }
+
+    // The highest the plate can be.
+    pub fn highest_position(&self) -> Distance {
+        Distance::from_um(self.position_um.max(self.lift_top_um))
+    }
+}
+
+// The print that was interrupted by a power loss, found at boot. The UI
+// offers to resume it.
+pub static RESUMABLE_PRINT: Mutex<Cell<Option<Checkpoint>>> = Mutex::new(Cell::new(None));
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Record {
+    magic: u32,
+    sequence: u32,
+    // 0 when the print completed or was aborted, there's nothing to resume.
+    active: u32,
+    checkpoint: Checkpoint,
+    // Records are aligned on flash pages, they never straddle two of them.
+    _reserved: [u32; 5],
+    checksum: u32,
+}
+
+const _: () = assert!(size_of::<Record>() == RECORD_SIZE as usize);
+
+impl Record {
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const _ as *const u8, size_of::<Self>()) }
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        let bytes = self.as_bytes();
+        fnv1a(&bytes[..bytes.len() - size_of::<u32>()])
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.checksum == self.compute_checksum()
+    }
+}
+
+// A flash region of CHECKPOINT_NUM_SECTORS sectors. Offsets are relative to
+// the start of the region.
+pub trait CheckpointStorage {
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> bool;
+    // The bytes must have been erased.
+    fn program(&mut self, offset: u32, buf: &[u8]) -> bool;
+    fn erase_sector(&mut self, sector: u32) -> bool;
+}
+
+pub struct CheckpointLog {
+    storage: &'static mut dyn CheckpointStorage,
+    // Where the next record goes.
+    next_slot: u32,
+    next_sequence: u32,
+}
+
+impl CheckpointLog {
+    // Also returns the checkpoint of the print to resume, if any.
+    pub fn open(storage: &'static mut dyn CheckpointStorage) -> (Self, Option<Checkpoint>) {
+        let mut latest: Option<(u32, Record)> = None;
+
+        for slot in 0..NUM_RECORDS {
+            if let Some(record) = read_record(storage, slot) {
+                if latest.map(|(_, l)| record.sequence > l.sequence).unwrap_or(true) {
+                    latest = Some((slot, record));
+                }
+            }
+        }
+
+        let (next_slot, next_sequence, checkpoint) = match latest {
+            Some((slot, record)) => (
+                (slot + 1) % NUM_RECORDS,
+                record.sequence.wrapping_add(1),
+                if record.active != 0 { Some(record.checkpoint) } else { None },
+            ),
+            None => (0, 0, None),
+        };
+
+        if let Some(checkpoint) = checkpoint.as_ref() {
+            info!("Found a print checkpoint: {:?}", checkpoint);
+        }
+
+        (Self { storage, next_slot, next_sequence }, checkpoint)
+    }
+
+    pub fn save(&mut self, checkpoint: &Checkpoint) {
+        self.append(checkpoint, true);
+    }
+
+    // There's nothing to resume anymore.
+    pub fn clear(&mut self) {
+        let empty = Checkpoint::new(&FileName::new("-").unwrap(), 0, Distance::default());
+        self.append(&empty, false);
+    }
+
+    fn append(&mut self, checkpoint: &Checkpoint, active: bool) {
+        let mut record = Record {
+            magic: MAGIC,
+            sequence: self.next_sequence,
+            active: active as u32,
+            checkpoint: *checkpoint,
+            _reserved: [0; 5],
+            checksum: 0,
+        };
+        record.checksum = record.compute_checksum();
+
+        // A record may have been partially written when the power went out.
+        // We skip over slots that are not erased. This terminates, the sector
+        // of the slot gets erased when we get to its start.
+        for _ in 0..NUM_RECORDS {
+            let slot = self.next_slot;
+            self.next_slot = (slot + 1) % NUM_RECORDS;
+
+            if slot % RECORDS_PER_SECTOR == 0 {
+                // The oldest records go, the latest one is in the previous sector.
+                if !self.storage.erase_sector(slot / RECORDS_PER_SECTOR) {
+                    error!("Failed to erase the checkpoint sector");
+                    return;
+                }
+            } else if !self.is_erased(slot) {
+                continue;
+            }
+
+            if !self.storage.program(slot * RECORD_SIZE, record.as_bytes()) {
+                error!("Failed to write the print checkpoint");
+            }
+            self.next_sequence = self.next_sequence.wrapping_add(1);
+            return;
+        }
+    }
+
+    fn is_erased(&mut self, slot: u32) -> bool {
+        let mut buf = [0u8; RECORD_SIZE as usize];
+        self.storage.read(slot * RECORD_SIZE, &mut buf) && buf.iter().all(|b| *b == 0xFF)
+    }
+}
+
+fn read_record(storage: &mut dyn CheckpointStorage, slot: u32) -> Option<Record> {
+    let mut record = MaybeUninit::<Record>::uninit();
+    let buf = unsafe { core::slice::from_raw_parts_mut(
+        record.as_mut_ptr() as *mut u8,
+        size_of::<Record>(),
+    )};
+
+    if !storage.read(slot * RECORD_SIZE, buf) {
+        return None;
+    }
+
+    // Any bit pattern is valid for Record
+    let record = unsafe { record.assume_init() };
+    Some(record).filter(Record::is_valid)
+}
+
+#[cfg(feature="saturn")]
+mod ext_flash_storage {
+    use spi_memory::prelude::*;
+
+    use crate::consts::{ext_flash::CHECKPOINTS_OFFSET, print::CHECKPOINT_SECTOR_SIZE};
+    use crate::drivers::ext_flash::ExtFlash;
+
+    use super::CheckpointStorage;
+
+    impl CheckpointStorage for ExtFlash {
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> bool {
+            self.0.read(CHECKPOINTS_OFFSET + offset, buf).is_ok()
+        }
+
+        fn program(&mut self, offset: u32, buf: &[u8]) -> bool {
+            let mut buf = heapless::Vec::<u8, 256>::from_slice(buf)
+                .expect("Records must fit in a flash page");
+            self.0.write_bytes(CHECKPOINTS_OFFSET + offset, &mut buf).is_ok()
+        }
+
+        fn erase_sector(&mut self, sector: u32) -> bool {
+            self.0.erase_sectors(CHECKPOINTS_OFFSET + sector * CHECKPOINT_SECTOR_SIZE, 1).is_ok()
+        }
+    }
+}
+
+// Behaves like a NOR flash: erasing sets the bytes to 0xFF, and programming
+// can only clear bits.
+#[cfg(feature="simulator")]
+pub struct FileCheckpointStorage(pub std::path::PathBuf);
+
+#[cfg(feature="simulator")]
+impl FileCheckpointStorage {
+    fn load(&self) -> std::vec::Vec<u8> {
+        let size = (CHECKPOINT_NUM_SECTORS * CHECKPOINT_SECTOR_SIZE) as usize;
+        match std::fs::read(&self.0) {
+            Ok(content) if content.len() == size => content,
+            _ => std::vec![0xFF; size],
+        }
+    }
+}
+
+#[cfg(feature="simulator")]
+impl CheckpointStorage for FileCheckpointStorage {
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> bool {
+        let content = self.load();
+        let offset = offset as usize;
+        buf.copy_from_slice(&content[offset..offset + buf.len()]);
+        true
+    }
+
+    fn program(&mut self, offset: u32, buf: &[u8]) -> bool {
+        let mut content = self.load();
+        let offset = offset as usize;
+        for (dst, src) in content[offset..offset + buf.len()].iter_mut().zip(buf) {
+            *dst &= *src;
+        }
+        std::fs::write(&self.0, content).is_ok()
+    }
+
+    fn erase_sector(&mut self, sector: u32) -> bool {
+        let mut content = self.load();
+        let start = (sector * CHECKPOINT_SECTOR_SIZE) as usize;
+        content[start..start + CHECKPOINT_SECTOR_SIZE as usize].fill(0xFF);
+        std::fs::write(&self.0, content).is_ok()
+    }
+}
@@ -0,0 +1,60 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_util::channel::signal::Signal;
+
+use crate::util::TaskRunner;
+
+use super::PrintTask;
+
+// Requests from the UI to the running print. A pause takes effect between two
+// layers, once the current exposure is over. An abort is a cancellation of the
+// print task, it takes effect right away.
+pub struct PrintControl {
+    pause_requested: AtomicBool,
+    resume_signal: Signal<()>,
+}
+
+pub static PRINT_CONTROL: PrintControl = PrintControl::new();
+
+impl PrintControl {
+    const fn new() -> Self {
+        Self {
+            pause_requested: AtomicBool::new(false),
+            resume_signal: Signal::new(),
+        }
+    }
+
+    pub fn pause(&self) {
+        self.resume_signal.reset();
+        self.pause_requested.store(true, Ordering::Release);
+    }
+
+    pub fn resume(&self) {
+        self.pause_requested.store(false, Ordering::Release);
+        self.resume_signal.signal(());
+    }
+
+    pub fn abort(&self, task_runner: &TaskRunner<PrintTask>) {
+        self.pause_requested.store(false, Ordering::Release);
+        task_runner.cancel_task();
+    }
+
+    pub fn is_pause_requested(&self) -> bool {
+        self.pause_requested.load(Ordering::Acquire)
+    }
+
+    // Resolves when resume() is called.
+    pub async fn wait_for_resume(&self) {
+        while self.is_pause_requested() {
+            self.resume_signal.wait().await;
+        }
+    }
+
+    // Forgets the requests of a previous print.
+    pub(super) fn reset(&self) {
+        self.pause_requested.store(false, Ordering::Release);
+        self.resume_signal.reset();
+    }
+}
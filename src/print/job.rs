@@ -0,0 +1,450 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::cell::Cell;
+
+use embassy_time::{Duration, Timer};
+use embassy_util::blocking_mutex::CriticalSectionMutex as Mutex;
+
+use crate::consts::{print::*, uv_light::DEFAULT_PWM};
+use crate::settings::Settings;
+use crate::drivers::{
+    lcd::{Lcd, MaskLcd},
+    uv_light::{UvExposure, UvPwm, UvCalibration},
+    zaxis::{
+        self, prelude::*,
+        MotionControlAsync, MoveParams, MoveError, HomingError, LiftRetract, Stage,
+        PeelController, PeelConfig, LiftPolicy, CuredArea,
+    },
+    load_cell::FORCE_MONITOR,
+};
+use crate::file_formats::ctb;
+use crate::util::io::{self as io, FsFile};
+
+use super::{PRINT_CONTROL, FileName, Checkpoint, CheckpointLog};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PrintState {
+    Idle,
+    Homing,
+    // Finding the origin from the checkpoint, without going down, see
+    // zaxis::recover_origin().
+    Recovering,
+    // Going down to the first layer.
+    Descending,
+    // The states of a layer, in order.
+    Drawing(u32),
+    Exposing(u32),
+    LiftingAndRetracting(u32),
+    Waiting(u32),
+    // The plate is up, waiting for the resume, see PrintControl.
+    Pausing(u32),
+    Paused(u32),
+    Resuming(u32),
+    // Raising the plate out of the vat.
+    Finishing,
+    Done,
+    Failed,
+    Aborted,
+}
+
+// What the UI shows.
+pub static PRINT_STATE: Mutex<Cell<PrintState>> = Mutex::new(Cell::new(PrintState::Idle));
+
+pub fn print_state() -> PrintState {
+    PRINT_STATE.lock(|s| s.get())
+}
+
+#[derive(Debug)]
+pub enum PrintError {
+    File(io::Error),
+    // Not a CTB file we can read.
+    InvalidFile,
+    // Past the last layer of the file.
+    InvalidStartLayer(u32),
+    Homing(HomingError),
+    Move(MoveError),
+    // The driver of the UV light is a stub, nothing would get cured.
+    UvLightUnavailable,
+}
+
+impl From<io::Error> for PrintError {
+    fn from(e: io::Error) -> Self {
+        PrintError::File(e)
+    }
+}
+
+impl From<HomingError> for PrintError {
+    fn from(e: HomingError) -> Self {
+        PrintError::Homing(e)
+    }
+}
+
+impl From<MoveError> for PrintError {
+    fn from(e: MoveError) -> Self {
+        PrintError::Move(e)
+    }
+}
+
+// How a layer is printed, from the print file.
+#[derive(Clone, Copy)]
+pub struct LayerParams {
+    pub position: Distance,
+    pub exposure: Duration,
+    // Rest after the plate comes back down, before the next exposure.
+    pub light_off: Duration,
+    pub lift_retract: LiftRetract,
+    pub pwm: UvPwm,
+}
+
+fn duration_from_sec(sec: f32) -> Duration {
+    Duration::from_millis((sec * 1000.0) as u64)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum PrintStart {
+    // Homes, and goes straight to the position of the layer. The earlier
+    // layers are skipped. Homing goes down to the bottom sensor, the build
+    // plate must be empty.
+    Layer(u32),
+    // After a power loss, the plate is in the vat, stuck to the print. We
+    // can't home, the plate is between `lowest` and `highest` from the
+    // checkpoint, see zaxis::recover_origin().
+    Resume { layer_index: u32, lowest: Distance, highest: Distance },
+}
+
+impl PrintStart {
+    fn layer_index(&self) -> u32 {
+        match self {
+            Self::Layer(layer_index) => *layer_index,
+            Self::Resume { layer_index, .. } => *layer_index,
+        }
+    }
+}
+
+pub struct PrintJob<'a> {
+    mc: &'a mut MotionControlAsync,
+    lcd: &'a mut Lcd,
+    uv: &'a UvExposure,
+    file: FsFile<'a>,
+    file_name: FileName,
+    checkpoints: Option<&'a mut CheckpointLog>,
+    header: ctb::Header,
+    print_settings: ctb::PrintSettings,
+    pause_height: Distance,
+    uv_calibration: UvCalibration,
+    // Picks the peel speed when there's a load cell.
+    peel: PeelController,
+    // Adapts the lift to the cured area of each layer, from the settings.
+    lift_policy: LiftPolicy,
+}
+
+impl<'a> PrintJob<'a> {
+    pub async fn new(
+        mc: &'a mut MotionControlAsync,
+        lcd: &'a mut Lcd,
+        uv: &'a UvExposure,
+        mut file: FsFile<'a>,
+        file_name: FileName,
+        checkpoints: Option<&'a mut CheckpointLog>,
+        settings: &Settings,
+    ) -> Result<PrintJob<'a>, PrintError> {
+        if !uv.is_functional() {
+            return Err(PrintError::UvLightUnavailable);
+        }
+
+        let header = file.read_obj::<ctb::Header>().await?;
+        header.check_magic().map_err(|_| PrintError::InvalidFile)?;
+
+        file.try_seek_from_start(header.print_settings_offset)?;
+        let print_settings = file.read_obj::<ctb::PrintSettings>().await?;
+
+        let num_layers = header.num_layers;
+        debug!("Print file: {} layers", num_layers);
+
+        let peel = PeelController::new(PeelConfig::default(),
+            print_settings.normal_lift_speed_mm_per_min / 60.0);
+
+        Ok(Self {
+            mc, lcd, uv, file, file_name, checkpoints, header, print_settings,
+            pause_height: settings.print_pause_height(),
+            uv_calibration: settings.uv_calibration,
+            peel,
+            lift_policy: settings.lift_policy(),
+        })
+    }
+
+    pub fn num_layers(&self) -> u32 {
+        self.header.num_layers
+    }
+
+    fn set_state(&self, state: PrintState) {
+        debug!("Print state: {:?}", state);
+        PRINT_STATE.lock(|s| s.set(state));
+    }
+
+    // Prints the layers from the start layer to the end.
+    pub async fn run(&mut self, start: PrintStart) -> Result<(), PrintError> {
+        PRINT_CONTROL.reset();
+        let result = self.print(start).await;
+        if result.is_ok() {
+            if let Some(checkpoints) = self.checkpoints.as_deref_mut() {
+                checkpoints.clear();
+            }
+        }
+        self.set_state(if result.is_ok() { PrintState::Done } else { PrintState::Failed });
+        result
+    }
+
+    async fn print(&mut self, start: PrintStart) -> Result<(), PrintError> {
+        let num_layers = self.num_layers();
+        let start_layer = start.layer_index();
+        if start_layer >= num_layers {
+            return Err(PrintError::InvalidStartLayer(start_layer));
+        }
+
+        self.uv.turn_off();
+        self.lcd.blank();
+
+        match start {
+            PrintStart::Layer(_) => {
+                self.set_state(PrintState::Homing);
+                {
+                    // Homing changes the speed and the acceleration profile.
+                    let mut mc = self.mc.settings_guard();
+                    zaxis::calibrate_origin(&mut mc, None).await?;
+                }
+
+                self.set_state(PrintState::Descending);
+                let (_, first) = self.read_layer(start_layer).await?;
+                self.move_to(first.position, DESCEND_SPEED_MM_PER_SEC).await?;
+            }
+            PrintStart::Resume { lowest, highest, .. } => {
+                self.set_state(PrintState::Recovering);
+                zaxis::recover_origin(self.mc, lowest.steps(), highest.steps()).await?;
+
+                // Peels the print off the FEP, and comes back down to the
+                // layer, never lower than where the plate rested.
+                let (_, first) = self.read_layer(start_layer).await?;
+                zaxis::lift_and_retract(self.mc, &first.lift_retract, first.position.steps()).await?;
+            }
+        }
+
+        if start_layer > 0 {
+            info!("Starting at layer {}/{}", start_layer, num_layers);
+        }
+
+        for layer_index in start_layer..num_layers {
+            self.print_layer(layer_index).await?;
+        }
+
+        self.set_state(PrintState::Finishing);
+        let (_, top) = self.mc.get_limits();
+        self.mc.move_to(top, &MoveParams::new(FINISH_SPEED_MM_PER_SEC.mm()))?;
+        self.mc.wait(zaxis::Event::Idle).await;
+
+        Ok(())
+    }
+
+    // The plate is at the position of the layer when we get here, and at the
+    // position of the next layer when we are done.
+    async fn print_layer(&mut self, layer_index: u32) -> Result<(), PrintError> {
+        let (layer, params) = self.read_layer(layer_index).await?;
+
+        if PRINT_CONTROL.is_pause_requested() {
+            self.pause(layer_index, &params).await?;
+        }
+
+        self.set_state(PrintState::Drawing(layer_index));
+        let cured_area = self.draw_layer(&layer, layer_index).await?;
+
+        self.set_state(PrintState::Exposing(layer_index));
+        if let Some(irradiance) = self.uv_calibration.irradiance(params.pwm) {
+            let dose = irradiance * params.exposure.as_millis() as f32 / 1000.0;
+            debug!("Layer {}: {:.2}mW/cm², {:.1}mJ/cm²", layer_index, irradiance, dose);
+        }
+        self.uv.expose(params.exposure, params.pwm).await;
+        self.lcd.blank();
+
+        if layer_index + 1 < self.num_layers() {
+            let (_, next) = self.read_layer(layer_index + 1).await?;
+
+            let area_mm2 = cured_area.mm2(self.header.pixel_area_mm2());
+            let motion = self.lift_policy.layer_motion(area_mm2, &params.lift_retract);
+            debug!("Layer {}: {:.0}mm² cured", layer_index, area_mm2);
+
+            // Until the plate rests again, its position is not exactly known.
+            let lift = motion.lift_retract.lift_slow.distance + motion.lift_retract.lift_fast.distance;
+            let top = params.position + Distance::from_mm(lift.as_mm());
+            self.save_checkpoint(&Checkpoint::lifting(&self.file_name, layer_index, params.position, top));
+
+            self.set_state(PrintState::LiftingAndRetracting(layer_index));
+            if FORCE_MONITOR.is_present() {
+                zaxis::lift_and_retract_with_feedback(self.mc, &motion.lift_retract,
+                    next.position.steps(), &FORCE_MONITOR, &mut self.peel).await?;
+            } else {
+                zaxis::lift_and_retract(self.mc, &motion.lift_retract, next.position.steps()).await?;
+            }
+
+            // The plate rests, its position is known.
+            self.save_checkpoint(&Checkpoint::new(&self.file_name, layer_index, next.position));
+
+            self.set_state(PrintState::Waiting(layer_index));
+            // The policy can only wait longer than the file asks.
+            Timer::after(next.light_off.max(motion.wait)).await;
+        }
+
+        Ok(())
+    }
+
+    // A pause requested during an exposure takes effect here, at the next
+    // layer, once the plate is in position for it. The plate goes up to the
+    // pause height, and comes back down to the exact same position.
+    async fn pause(&mut self, layer_index: u32, params: &LayerParams) -> Result<(), PrintError> {
+        let position = self.mc.get_current_position();
+        let (_, top) = self.mc.get_limits();
+        let pause_position = self.pause_height.steps().min(top);
+
+        // Same as a lift, for the checkpoints. There's nothing to resume
+        // before the first layer.
+        let last_layer = layer_index.checked_sub(1);
+        if let Some(last_layer) = last_layer {
+            let top = Distance::from_mm(pause_position.as_mm());
+            self.save_checkpoint(&Checkpoint::lifting(&self.file_name, last_layer, params.position, top));
+        }
+
+        self.set_state(PrintState::Pausing(layer_index));
+        if pause_position > position {
+            // Same speed as the lift, the plate may still stick to the FEP.
+            let speed = params.lift_retract.lift_slow.params.max_speed;
+            self.mc.move_to(pause_position, &MoveParams::new(speed))?;
+            self.mc.wait(zaxis::Event::Idle).await;
+        }
+
+        self.set_state(PrintState::Paused(layer_index));
+        PRINT_CONTROL.wait_for_resume().await;
+
+        self.set_state(PrintState::Resuming(layer_index));
+        let speed = params.lift_retract.retract_slow.params.max_speed;
+        self.mc.move_to(position, &MoveParams::new(speed))?;
+        self.mc.wait(zaxis::Event::Idle).await;
+
+        if let Some(last_layer) = last_layer {
+            self.save_checkpoint(&Checkpoint::new(&self.file_name, last_layer, params.position));
+        }
+
+        // The resin needs to settle again.
+        Timer::after(params.light_off).await;
+
+        Ok(())
+    }
+
+    fn save_checkpoint(&mut self, checkpoint: &Checkpoint) {
+        if let Some(checkpoints) = self.checkpoints.as_deref_mut() {
+            checkpoints.save(checkpoint);
+        }
+    }
+
+    // Returns the area that the layer cures.
+    async fn draw_layer(&mut self, layer: &ctb::Layer, layer_index: u32) -> Result<CuredArea, PrintError> {
+        let xor_key = self.header.xor_key;
+        let mut canvas = self.lcd.draw();
+        let mut cured_area = CuredArea::default();
+        layer.for_each_pixels(&mut self.file, layer_index, xor_key, |color, repeat| {
+            cured_area.add_run(color, repeat);
+            canvas.push_pixels(color, repeat);
+        }).await?;
+        Ok(cured_area)
+    }
+
+    async fn read_layer(&mut self, layer_index: u32) -> Result<(ctb::Layer, LayerParams), PrintError> {
+        let offset = self.header.layers_offset + layer_index * core::mem::size_of::<ctb::Layer>() as u32;
+        self.file.try_seek_from_start(offset)?;
+        let layer = self.file.read_obj::<ctb::Layer>().await?;
+
+        let bottom = layer_index < self.print_settings.bottom_layer_count;
+        let layer_ex = self.read_layer_ex(&layer).await?;
+        let lift_retract = self.lift_retract(layer_ex.as_ref(), bottom);
+        let pwm = self.pwm(layer_ex.as_ref(), bottom);
+
+        let params = LayerParams {
+            position: Distance::from_mm(layer.position_z_mm),
+            exposure: duration_from_sec(layer.exposure_time_sec),
+            light_off: duration_from_sec(layer.light_off_sec),
+            lift_retract,
+            pwm,
+        };
+
+        Ok((layer, params))
+    }
+
+    // Only in files of version 3 and above.
+    async fn read_layer_ex(&mut self, layer: &ctb::Layer) -> Result<Option<ctb::LayerEx>, PrintError> {
+        if !self.header.has_layer_ex() {
+            return Ok(None);
+        }
+        let offset = layer.layer_ex_offset().ok_or(io::Error::EndOfFile)?;
+        self.file.try_seek_from_start(offset)?;
+        Ok(Some(self.file.read_obj::<ctb::LayerEx>().await?))
+    }
+
+    // The layer gives the two stages of the lift and of the retract (TSMC).
+    // Without it, the header gives a single stage for each.
+    fn lift_retract(&self, layer_ex: Option<&ctb::LayerEx>, bottom: bool) -> LiftRetract {
+        // Slicers leave the second stage at 0 when TSMC is off.
+        fn speed_or(speed_mm_per_min: f32, fallback_mm_per_min: f32) -> f32 {
+            if speed_mm_per_min > 0.0 { speed_mm_per_min } else { fallback_mm_per_min }
+        }
+
+        if let Some(ex) = layer_ex {
+            let lift_speed = ex.lift_speed_mm_per_min;
+            let retract_speed = ex.retract_speed_mm_per_min;
+            return LiftRetract {
+                lift_slow: Stage::gentle(ex.lift_height_mm, lift_speed / 60.0),
+                lift_fast: Stage::new(ex.lift_height2_mm, speed_or(ex.lift_speed2_mm_per_min, lift_speed) / 60.0),
+                retract_fast: Stage::new(0.0, retract_speed / 60.0),
+                retract_slow: Stage::gentle(ex.retract_height2_mm, speed_or(ex.retract_speed2_mm_per_min, retract_speed) / 60.0),
+            };
+        }
+
+        let s = &self.print_settings;
+        let (lift_height, lift_speed) = if bottom {
+            (s.bottom_lift_height_mm, s.bottom_lift_speed_mm_per_min)
+        } else {
+            (s.normal_lift_height_mm, s.normal_lift_speed_mm_per_min)
+        };
+        let retract_speed = s.normal_retract_speed_mm_per_min;
+
+        LiftRetract {
+            lift_slow: Stage::gentle(lift_height, lift_speed / 60.0),
+            lift_fast: Stage::new(0.0, lift_speed / 60.0),
+            retract_fast: Stage::new(0.0, retract_speed / 60.0),
+            retract_slow: Stage::gentle(0.0, retract_speed / 60.0),
+        }
+    }
+
+    // The layer says, or else the header does. Older files have no UV power
+    // in the header. The calibration corrects the response of the LEDs.
+    fn pwm(&self, layer_ex: Option<&ctb::LayerEx>, bottom: bool) -> UvPwm {
+        self.uv_calibration.linearize(self.file_pwm(layer_ex, bottom))
+    }
+
+    fn file_pwm(&self, layer_ex: Option<&ctb::LayerEx>, bottom: bool) -> UvPwm {
+        if let Some(light_pwm) = layer_ex.map(|ex| ex.light_pwm) {
+            if light_pwm > 0.0 {
+                return (light_pwm + 0.5).min(UvPwm::MAX as f32) as UvPwm;
+            }
+        }
+
+        let power = if bottom { self.header.bottom_uv_power } else { self.header.normal_uv_power };
+        match power {
+            0 => DEFAULT_PWM,
+            _ => power.min(UvPwm::MAX as u16) as UvPwm,
+        }
+    }
+
+    async fn move_to(&mut self, position: Distance, speed_mm_per_sec: f32) -> Result<(), PrintError> {
+        self.mc.move_to(position.steps(), &MoveParams::new(speed_mm_per_sec.mm()))?;
+        self.mc.wait(zaxis::Event::Idle).await;
+        Ok(())
+    }
+}
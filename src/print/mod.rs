@@ -0,0 +1,19 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Prints a CTB file: homes the Z-axis, goes down to the first layer, and for
+// each layer draws the mask on the LCD, exposes, then lifts and retracts to the
+// next layer. The print runs as a task of its own, so that the UI can follow
+// its state, pause it and abort it. Checkpoints saved after each layer let a
+// print resume after a power loss.
+
+mod job;
+pub use job::*;
+
+mod task;
+pub use task::*;
+
+mod control;
+pub use control::*;
+
+mod checkpoint;
+pub use checkpoint::*;
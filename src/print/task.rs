@@ -0,0 +1,144 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::future::Future;
+
+use embedded_sdmmc::Mode;
+
+use crate::consts::print::*;
+use crate::drivers::{
+    lcd::{Lcd, MaskLcd},
+    uv_light::UvExposure,
+    usb::UsbHost,
+    zaxis::{self, prelude::*, MotionControlAsync, MoveParams},
+};
+use crate::settings::Settings;
+use crate::util::CancellableTask;
+
+use super::{PrintJob, PrintStart, PrintError, PrintState, PRINT_STATE, Checkpoint, CheckpointLog};
+
+// The USB drive is FAT formatted, we open files by their 8.3 name.
+pub const MAX_FILE_NAME_LEN: usize = 12;
+
+// A file name that can be copied around with the task.
+#[derive(Clone, Copy)]
+pub struct FileName {
+    buf: [u8; MAX_FILE_NAME_LEN],
+    len: usize,
+}
+
+impl FileName {
+    pub fn new(name: &str) -> Result<Self, ()> {
+        if name.is_empty() || name.len() > MAX_FILE_NAME_LEN {
+            return Err(());
+        }
+        let mut buf = [0; MAX_FILE_NAME_LEN];
+        buf[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(Self { buf, len: name.len() })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Copied from a &str
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+impl core::fmt::Debug for FileName {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+pub struct PrintContext {
+    pub zaxis: &'static mut MotionControlAsync,
+    pub lcd: &'static mut Lcd,
+    pub usb_host: &'static mut UsbHost,
+    pub uv: &'static UvExposure,
+    // None when the printer has no storage for them.
+    pub checkpoints: Option<&'static mut CheckpointLog>,
+    pub settings: Settings,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PrintTask {
+    Print { file_name: FileName, start_layer: u32 },
+    // After a power loss
+    Resume(Checkpoint),
+}
+
+async fn print_file(ctx: &mut PrintContext, file_name: &FileName, start: PrintStart) -> Result<(), PrintError> {
+    let mut fs = ctx.usb_host.wait_for_filesystem().await?;
+    let file = fs.open(file_name.as_str(), Mode::ReadOnly).await?;
+    // This print overwrites the checkpoints, the interrupted one can't be
+    // resumed anymore.
+    super::RESUMABLE_PRINT.lock(|c| c.set(None));
+    let checkpoints = ctx.checkpoints.as_deref_mut();
+    let mut job = PrintJob::new(ctx.zaxis, ctx.lcd, ctx.uv, file, *file_name, checkpoints, &ctx.settings).await?;
+    job.run(start).await
+}
+
+impl CancellableTask for PrintTask {
+    type Context = PrintContext;
+
+    type RunFuture<'a> = impl Future<Output = ()> + 'a where Self: 'a;
+    type CancelFuture<'a> = impl Future<Output = ()> + 'a where Self: 'a;
+
+    fn run<'a>(&'a self, ctx: &'a mut PrintContext) -> Self::RunFuture<'a> {
+        async move {
+            let (file_name, start) = match self {
+                Self::Print { file_name, start_layer } => (*file_name, PrintStart::Layer(*start_layer)),
+                Self::Resume(checkpoint) => match checkpoint.file_name() {
+                    Some(file_name) => (file_name, PrintStart::Resume {
+                        layer_index: checkpoint.layer_index + 1,
+                        lowest: checkpoint.position(),
+                        highest: checkpoint.highest_position(),
+                    }),
+                    None => {
+                        error!("Invalid checkpoint: {:?}", checkpoint);
+                        return;
+                    }
+                },
+            };
+
+            info!("Printing {:?}", file_name);
+            match print_file(ctx, &file_name, start).await {
+                Ok(()) => info!("Print complete"),
+                Err(e) => {
+                    // The checkpoint stays, the print can be resumed.
+                    error!("Print failed: {:?}", e);
+                    ctx.uv.turn_off();
+                    ctx.lcd.blank();
+                    PRINT_STATE.lock(|s| s.set(PrintState::Failed));
+                }
+            }
+        }
+    }
+
+    // Aborts the print, see PrintControl::abort().
+    fn cancel<'a>(&'a self, ctx: &'a mut PrintContext) -> Self::CancelFuture<'a> {
+        async move {
+            // Dropping the run future already turned off the light. The mask
+            // must not stay on the last drawn layer either.
+            ctx.uv.turn_off();
+            ctx.lcd.blank();
+            ctx.zaxis.stop();
+            ctx.zaxis.wait(zaxis::Event::Idle).await;
+
+            // Out of the vat, so that the print can be removed. Without a
+            // home position, we don't know how far we can go.
+            if ctx.zaxis.is_homed() {
+                let (_, top) = ctx.zaxis.get_limits();
+                let params = MoveParams::new(FINISH_SPEED_MM_PER_SEC.mm());
+                match ctx.zaxis.move_to(top, &params) {
+                    Ok(()) => ctx.zaxis.wait(zaxis::Event::Idle).await,
+                    Err(e) => warn!("Failed to raise the plate: {:?}", e),
+                }
+            }
+
+            if let Some(checkpoints) = ctx.checkpoints.as_deref_mut() {
+                checkpoints.clear();
+            }
+
+            PRINT_STATE.lock(|s| s.set(PrintState::Aborted));
+        }
+    }
+}
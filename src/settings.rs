@@ -0,0 +1,290 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// User settings that survive reboots. They are stored as a single record with
+// a magic number and a checksum. When the record is missing or corrupted, we
+// go with the defaults.
+// The saturn stores them on the external flash, the simulator in a file.
+// The mono4k has no storage for them yet, and always uses the defaults.
+
+use core::mem::MaybeUninit;
+use core::str::FromStr;
+
+use crate::consts::print::PAUSE_HEIGHT_MM;
+use crate::drivers::{
+    uv_light::{UvCalibration, UvCalibrationPoint, MAX_UV_CALIBRATION_POINTS},
+    zaxis::{prelude::*, LiftPoint, LiftPolicy, MAX_LIFT_POLICY_POINTS},
+};
+
+const MAGIC: u32 = 0x5e77_1265;
+// Bump when the layout of Settings changes. Older records are discarded.
+const VERSION: u32 = 4;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    // Compensates the play of the Z-axis lead screw nut on direction reversals.
+    pub zaxis_backlash_um: u32,
+    // Where the plate goes when a print is paused, for inspection.
+    pub print_pause_height_um: u32,
+    // Measured irradiance of the UV light for a few PWM duties.
+    pub uv_calibration: UvCalibration,
+    // The curve of the lift policy, see zaxis::lift_policy. Only the first
+    // `lift_curve_len` points are used, none means DEFAULT_CURVE.
+    pub lift_curve: [LiftPoint; MAX_LIFT_POLICY_POINTS],
+    pub lift_curve_len: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            zaxis_backlash_um: 0,
+            print_pause_height_um: Distance::from_mm(PAUSE_HEIGHT_MM).um() as u32,
+            uv_calibration: UvCalibration::default(),
+            lift_curve: [LiftPoint::default(); MAX_LIFT_POLICY_POINTS],
+            lift_curve_len: 0,
+        }
+    }
+}
+
+impl Settings {
+    pub fn zaxis_backlash(&self) -> Steps {
+        Distance::from_um(self.zaxis_backlash_um as i32).steps()
+    }
+
+    pub fn print_pause_height(&self) -> Distance {
+        Distance::from_um(self.print_pause_height_um as i32)
+    }
+
+    pub fn lift_policy(&self) -> LiftPolicy {
+        // lift_curve_len comes from the flash, it may be anything.
+        let len = (self.lift_curve_len as usize).min(MAX_LIFT_POLICY_POINTS);
+        if len == 0 {
+            return LiftPolicy::default();
+        }
+        LiftPolicy::new(&self.lift_curve[..len]).unwrap_or_else(|_| {
+            warn!("Invalid lift curve in the settings, using the default one");
+            LiftPolicy::default()
+        })
+    }
+
+    pub fn load(storage: &mut impl SettingsStorage) -> Self {
+        let mut record = MaybeUninit::<Record>::uninit();
+        let buf = unsafe { core::slice::from_raw_parts_mut(
+            record.as_mut_ptr() as *mut u8,
+            core::mem::size_of::<Record>(),
+        )};
+
+        if !storage.read(buf) {
+            debug!("No settings found, using the defaults");
+            return Self::default();
+        }
+
+        // Any bit pattern is valid for Record
+        let record = unsafe { record.assume_init() };
+        if record.magic != MAGIC || record.version != VERSION || record.checksum != record.compute_checksum() {
+            warn!("Stored settings are invalid or outdated, using the defaults");
+            return Self::default();
+        }
+
+        record.settings
+    }
+
+    pub fn save(&self, storage: &mut impl SettingsStorage) {
+        let mut record = Record { magic: MAGIC, version: VERSION, settings: *self, checksum: 0 };
+        record.checksum = record.compute_checksum();
+
+        if !storage.write(record.as_bytes()) {
+            error!("Failed to save the settings");
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SettingsError {
+    UnknownKey,
+    InvalidValue,
+}
+
+impl Settings {
+    // Changes a setting from its text form, e.g. "zaxis_backlash_um=20". The
+    // simulator takes these on the command line, see simulator::Config.
+    pub fn set(&mut self, assignment: &str) -> Result<(), SettingsError> {
+        let (key, value) = assignment.split_once('=').ok_or(SettingsError::InvalidValue)?;
+        match key.trim() {
+            "zaxis_backlash_um" => self.zaxis_backlash_um = parse(value)?,
+            "print_pause_height_mm" => {
+                let height = Distance::from_mm(parse(value)?);
+                if height.um() < 0 {
+                    return Err(SettingsError::InvalidValue);
+                }
+                self.print_pause_height_um = height.um() as u32;
+            }
+            // pwm:irradiance pairs, e.g. "0:0,128:4.1,255:5.2"
+            "uv_calibration" => {
+                let mut points = heapless::Vec::<UvCalibrationPoint, MAX_UV_CALIBRATION_POINTS>::new();
+                for point in value.split(',').filter(|p| !p.trim().is_empty()) {
+                    let (pwm, irradiance) = point.split_once(':').ok_or(SettingsError::InvalidValue)?;
+                    points.push(UvCalibrationPoint {
+                        pwm: parse(pwm)?,
+                        irradiance_mw_per_cm2: parse(irradiance)?,
+                    }).map_err(|_| SettingsError::InvalidValue)?;
+                }
+                self.uv_calibration = UvCalibration::new(&points).map_err(|_| SettingsError::InvalidValue)?;
+            }
+            // area:height:speed:wait points, e.g. "0:5:3:0.5,2000:6:1.5:1",
+            // see zaxis::lift_policy. Empty for the default curve.
+            "lift_curve" => {
+                let mut points = heapless::Vec::<LiftPoint, MAX_LIFT_POLICY_POINTS>::new();
+                for point in value.split(',').filter(|p| !p.trim().is_empty()) {
+                    let mut fields = [0.0f32; 4];
+                    let mut values = point.split(':');
+                    for field in &mut fields {
+                        *field = parse(values.next().ok_or(SettingsError::InvalidValue)?)?;
+                    }
+                    let [area_mm2, lift_height_mm, peel_speed_mm_per_sec, wait_sec] = fields;
+                    if values.next().is_some() || lift_height_mm < 0.0 ||
+                       peel_speed_mm_per_sec <= 0.0 || wait_sec < 0.0 {
+                        return Err(SettingsError::InvalidValue);
+                    }
+                    points.push(LiftPoint { area_mm2, lift_height_mm, peel_speed_mm_per_sec, wait_sec })
+                        .map_err(|_| SettingsError::InvalidValue)?;
+                }
+                if !points.is_empty() {
+                    LiftPolicy::new(&points).map_err(|_| SettingsError::InvalidValue)?;
+                }
+                self.lift_curve = [LiftPoint::default(); MAX_LIFT_POLICY_POINTS];
+                self.lift_curve[..points.len()].copy_from_slice(&points);
+                self.lift_curve_len = points.len() as u32;
+            }
+            _ => return Err(SettingsError::UnknownKey),
+        }
+        Ok(())
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, SettingsError> {
+    value.trim().parse().map_err(|_| SettingsError::InvalidValue)
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Record {
+    magic: u32,
+    version: u32,
+    settings: Settings,
+    checksum: u32,
+}
+
+impl Record {
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(
+            self as *const _ as *const u8,
+            core::mem::size_of::<Self>(),
+        )}
+    }
+
+    // Of everything but the checksum
+    fn compute_checksum(&self) -> u32 {
+        let bytes = self.as_bytes();
+        fnv1a(&bytes[..bytes.len() - core::mem::size_of::<u32>()])
+    }
+}
+
+// Also used by the print checkpoints.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, b| (hash ^ *b as u32).wrapping_mul(0x0100_0193))
+}
+
+pub trait SettingsStorage {
+    // Fills buf with the stored record. Returns false if there's none.
+    fn read(&mut self, buf: &mut [u8]) -> bool;
+    fn write(&mut self, buf: &[u8]) -> bool;
+}
+
+#[cfg(feature="saturn")]
+mod ext_flash_storage {
+    use spi_memory::prelude::*;
+
+    use crate::consts::ext_flash::SETTINGS_OFFSET;
+    use crate::drivers::ext_flash::ExtFlash;
+
+    use super::SettingsStorage;
+
+    impl SettingsStorage for ExtFlash {
+        // An erased flash reads as 0xFF, which fails the magic number check.
+        fn read(&mut self, buf: &mut [u8]) -> bool {
+            self.0.read(SETTINGS_OFFSET, buf).is_ok()
+        }
+
+        fn write(&mut self, buf: &[u8]) -> bool {
+            let mut buf = heapless::Vec::<u8, 256>::from_slice(buf)
+                .expect("Settings must fit in a flash page");
+            self.0.erase_sectors(SETTINGS_OFFSET, 1).is_ok() &&
+                self.0.write_bytes(SETTINGS_OFFSET, &mut buf).is_ok()
+        }
+    }
+}
+
+#[cfg(feature="simulator")]
+pub struct FileStorage(pub std::path::PathBuf);
+
+#[cfg(feature="simulator")]
+impl SettingsStorage for FileStorage {
+    fn read(&mut self, buf: &mut [u8]) -> bool {
+        match std::fs::read(&self.0) {
+            Ok(content) if content.len() == buf.len() => {
+                buf.copy_from_slice(&content);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> bool {
+        std::fs::write(&self.0, buf).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_save() {
+        let path = std::env::temp_dir().join(format!("settings_{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // Nothing stored yet.
+        let mut settings = Settings::load(&mut FileStorage(path.clone()));
+        assert_eq!(settings.zaxis_backlash_um, Settings::default().zaxis_backlash_um);
+
+        settings.set("zaxis_backlash_um=20").unwrap();
+        settings.set("print_pause_height_mm = 80.5").unwrap();
+        settings.set("uv_calibration=0:0,128:4,255:5").unwrap();
+        settings.set("lift_curve=0:4:2:0.5, 1000:8:1:2").unwrap();
+        assert_eq!(settings.set("lift_curve=0:4:2"), Err(SettingsError::InvalidValue));
+        assert_eq!(settings.set("lift_curve=0:4:0:1"), Err(SettingsError::InvalidValue));
+        assert_eq!(settings.set("uv_calibration=128:4,0:0"), Err(SettingsError::InvalidValue));
+        assert_eq!(settings.set("zaxis_backlash_um=-1"), Err(SettingsError::InvalidValue));
+        assert_eq!(settings.set("print_pause_height_mm"), Err(SettingsError::InvalidValue));
+        assert_eq!(settings.set("nope=1"), Err(SettingsError::UnknownKey));
+        settings.save(&mut FileStorage(path.clone()));
+
+        let loaded = Settings::load(&mut FileStorage(path.clone()));
+        assert_eq!(loaded.zaxis_backlash_um, 20);
+        assert_eq!(loaded.print_pause_height(), Distance::from_mm(80.5));
+        assert_eq!(loaded.uv_calibration.irradiance(128), Some(4.0));
+        let point = loaded.lift_policy().at(500.0);
+        assert_eq!(point.lift_height_mm, 6.0);
+        assert_eq!(point.wait_sec, 1.25);
+
+        // A corrupted record gives the defaults.
+        let mut content = std::fs::read(&path).unwrap();
+        content[8] ^= 1;
+        std::fs::write(&path, content).unwrap();
+        let loaded = Settings::load(&mut FileStorage(path.clone()));
+        assert_eq!(loaded.zaxis_backlash_um, 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
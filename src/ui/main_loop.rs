@@ -19,7 +19,10 @@ pub fn lvgl_init(display: RawDisplay) -> (Lvgl, Display<RawDisplay>) {
     use embedded_graphics::pixelcolor::Rgb565;
 
     let mut lvgl = Lvgl::new();
+    #[cfg(not(feature="simulator"))]
     lvgl.register_logger(|s| rtt_target::rprint!(s));
+    #[cfg(feature="simulator")]
+    lvgl.register_logger(|s| std::print!("{}", s));
     // Display init with its draw buffer
     static mut DRAW_BUFFER: [MaybeUninit<Rgb565>; LVGL_BUFFER_LEN] =
         [MaybeUninit::<Rgb565>::uninit(); LVGL_BUFFER_LEN];
@@ -56,7 +59,8 @@ pub fn idle_task(
     let mut ui = new_screen(&display, |screen| {
         let z_axis = unsafe { crate::Z_AXIS.steal() };
         let task_runner = unsafe { crate::TASK_RUNNER.steal() };
-        super::MoveZ::new(screen, task_runner, z_axis)
+        let print_task_runner = unsafe { crate::PRINT_TASK_RUNNER.steal() };
+        super::MoveZ::new(screen, task_runner, print_task_runner, z_axis)
     });
 
     display.load_screen(&mut ui);
@@ -1,5 +1,8 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use core::cell::Cell;
+
+use embassy_util::blocking_mutex::CriticalSectionMutex as Mutex;
 use futures::Future;
 use lvgl::{
     style::State,
@@ -16,9 +19,13 @@ use crate::{
         self,
         prelude::*,
     }, util::CancellableTask,
+    print::{PrintTask, RESUMABLE_PRINT},
 };
 use crate::consts::zaxis::motion_control::*;
 
+// Set by the MoveZero task when homing fails, shown until the next attempt.
+static HOMING_ERROR: Mutex<Cell<Option<zaxis::HomingError>>> = Mutex::new(Cell::new(None));
+
 pub struct MoveZ {
     btn_move_up: Btn<Self>,
     btn_move_down: Btn<Self>,
@@ -26,8 +33,13 @@ pub struct MoveZ {
     speed_label: Label<Self>,
     position_label: Label<Self>,
     btn_move_zero: Btn<Self>,
+    error_label: Label<Self>,
+    // The mono4k has no storage for the checkpoints, there's nothing to resume.
+    #[cfg(not(feature="mono4k"))]
+    btn_resume_print: Btn<Self>,
 
     task_runner: &'static TaskRunner<Task>,
+    print_task_runner: &'static TaskRunner<PrintTask>,
     zaxis: &'static zaxis::MotionControlAsync,
 }
 
@@ -35,6 +47,7 @@ impl MoveZ {
     pub fn new(
         screen: &mut Screen::<Self>,
         task_runner: &'static mut TaskRunner<Task>,
+        print_task_runner: &'static mut TaskRunner<PrintTask>,
         zaxis: &'static zaxis::MotionControlAsync,
     ) -> Self {
         use lvgl::widgets::*;
@@ -114,15 +127,38 @@ impl MoveZ {
             });
         });
 
+        let error_label = Label::new(screen).apply(|obj| { obj
+            .align_to(&btn_move_zero, Align::OutBottomMid, 0, spacing/2);
+        });
+
+        // Offered when a print was interrupted by a power loss.
+        #[cfg(not(feature="mono4k"))]
+        let btn_resume_print = Btn::new(screen).apply(|obj| {
+            Label::new(obj)
+                .set_text(&CStr::from_bytes_with_nul(b"Resume print\0").unwrap());
+            obj
+            .align_to(&error_label, Align::OutBottomMid, 0, spacing)
+            .on_event(Event::Clicked, |context| {
+                if let Some(checkpoint) = RESUMABLE_PRINT.lock(|c| c.take()) {
+                    if context.print_task_runner.enqueue_task(PrintTask::Resume(checkpoint)).is_err() {
+                        // Something else is printing, the offer stands.
+                        RESUMABLE_PRINT.lock(|c| c.set(Some(checkpoint)));
+                    }
+                }
+            });
+        });
+
         Label::new(screen).apply(|obj| { obj
             .set_text(&CStr::from_bytes_with_nul(b"Turbo Resin v0.1.3\0").unwrap())
             .align_to(screen, Align::BottomRight, -5, -5);
         });
 
         Self {
-            btn_move_up, btn_move_down, btn_move_zero,
+            btn_move_up, btn_move_down, btn_move_zero, error_label,
+            #[cfg(not(feature="mono4k"))]
+            btn_resume_print,
             speed_label, position_label, speed_slider,
-            task_runner, zaxis,
+            task_runner, print_task_runner, zaxis,
         }
     }
 
@@ -154,6 +190,23 @@ impl MoveZ {
             }
         }
 
+        #[cfg(not(feature="mono4k"))]
+        {
+            let can_resume = RESUMABLE_PRINT.lock(|c| c.get().is_some()) &&
+                self.print_task_runner.get_current_task().is_none();
+            if can_resume {
+                self.btn_resume_print.clear_state(State::DISABLED);
+            } else {
+                self.btn_resume_print.add_state(State::DISABLED);
+            }
+        }
+
+        let error = match HOMING_ERROR.lock(|e| e.get()) {
+            Some(e) => format!("Homing failed, check the Z-axis endstop ({:?})\0", e),
+            None => format!("\0"),
+        };
+        self.error_label.set_text(&CStr::from_bytes_with_nul(error.as_bytes()).unwrap());
+
         // set_text() makes a copy of the string internally.
         self.position_label.set_text(&CStr::from_bytes_with_nul(
             format!("Position: {:.2} mm\0", self.zaxis.get_current_position().as_mm()).as_bytes()
@@ -181,14 +234,25 @@ impl CancellableTask for Task {
     fn run<'a>(&'a self, mc: &'a mut zaxis::MotionControlAsync) -> Self::RunFuture<'a> {
         async move {
             match self {
-                Self::MoveUp => mc.set_target_relative(40.0.mm()),
-                Self::MoveDown => mc.set_target_relative((-40.0).mm()),
+                Self::MoveUp => mc.move_until_stopped(zaxis::Direction::Up, &zaxis::MoveParams::new(mc.get_max_speed())),
+                Self::MoveDown => mc.move_until_stopped(zaxis::Direction::Down, &zaxis::MoveParams::new(mc.get_max_speed())),
                 Self::MoveZero => {
-                    let s = mc.get_max_speed();
-                    zaxis::calibrate_origin(mc, None).await;
-                    // FIXME we don't restore the original speed when the task is cancelled.
-                    mc.set_max_speed(s);
-                    mc.set_target(0.0.mm());
+                    HOMING_ERROR.lock(|e| e.set(None));
+                    let result = {
+                        let mut mc = mc.settings_guard();
+                        zaxis::calibrate_origin(&mut mc, None).await
+                    };
+                    match result {
+                        Ok(()) => {
+                            if let Err(e) = mc.set_target(0.0.mm()) {
+                                warn!("Failed to move to Z=0: {:?}", e);
+                            }
+                        }
+                        Err(e) => {
+                            error!("Homing failed, check the Z-axis endstop: {:?}", e);
+                            HOMING_ERROR.lock(|c| c.set(Some(e)));
+                        }
+                    }
                 }
             };
             mc.wait(zaxis::Event::Idle).await;
@@ -3,11 +3,9 @@
 use embedded_sdmmc::{Timestamp, TimeSource, Controller, Volume, Directory, Mode};
 use crate::util::io::File;
 
-use crate::drivers::usb::{
-    Msc, UsbHost,
-    UsbResult, UsbError,
-    MscBlockDevice,
-};
+use crate::drivers::usb::{UsbHost, UsbError, MscBlockDevice};
+#[cfg(not(feature="simulator"))]
+use crate::drivers::usb::{Msc, UsbResult};
 
 pub type Error = embedded_sdmmc::Error<UsbError>;
 pub type Result<T> = core::result::Result<T, Error>;
@@ -27,6 +25,7 @@ impl From<MscBlockDevice> for TimelessController {
     }
 }
 
+#[cfg(not(feature="simulator"))]
 impl UsbHost {
     pub async fn wait_for_filesystem(&mut self) -> Result<FileSystem> {
         // An inner function just to make error handling easier.
@@ -36,10 +35,26 @@ impl UsbHost {
                 .into_block_device().await
         }
 
-        let mut fs: TimelessController = wait_for_usb_block_device(self).await
-            .map_err(embedded_sdmmc::Error::DeviceError)?.into();
+        let block_device = wait_for_usb_block_device(self).await
+            .map_err(embedded_sdmmc::Error::DeviceError)?;
 
         debug!("Disk initialized");
+        FileSystem::mount(block_device).await
+    }
+}
+
+pub struct FileSystem {
+    fs: Controller<MscBlockDevice, NullTimeSource>,
+    volume: Volume,
+    root: Directory,
+}
+
+pub type FsFile<'a> = File<'a, MscBlockDevice, NullTimeSource>;
+
+impl FileSystem {
+    pub async fn mount(block_device: MscBlockDevice) -> Result<Self> {
+        let mut fs: TimelessController = block_device.into();
+
         let volume = fs.get_volume(embedded_sdmmc::VolumeIdx(0)).await?;
         trace!("{:#?}", volume);
         let root = fs.open_root_dir(&volume)?;
@@ -54,17 +69,7 @@ impl UsbHost {
 
         Ok(FileSystem { fs, volume, root })
     }
-}
-
-pub struct FileSystem {
-    fs: Controller<MscBlockDevice, NullTimeSource>,
-    volume: Volume,
-    root: Directory,
-}
 
-type FsFile<'a> = File<'a, MscBlockDevice, NullTimeSource>;
-
-impl FileSystem {
     pub async fn open<'a>(&'a mut self, filename: &str, mode: Mode) -> Result<FsFile> {
         File::new(&mut self.fs, &mut self.volume, &self.root, filename, mode).await
     }
@@ -35,6 +35,13 @@ impl<'b, D: BlockDevice + 'static, T: TimeSource + 'static> File<'b, D, T> {
         Ok(Self { inner, fs, volume })
     }
 
+    // Same as Seek::seek_from_start(), but fails instead of panicking when
+    // `pos` is past the end of the file, e.g. with an offset from a corrupted
+    // header.
+    pub fn try_seek_from_start(&mut self, pos: u32) -> Result<(), Error<D::Error>> {
+        self.inner.seek_from_start(pos).map_err(|_| Error::EndOfFile)
+    }
+
     impl_read_obj!(File<'b, D, T>);
     impl_write_obj!(File<'b, D, T>);
 }
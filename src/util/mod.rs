@@ -6,11 +6,15 @@ pub use task_runner::*;
 mod shared_with_interrupt;
 pub use shared_with_interrupt::*;
 
+#[cfg(not(feature="simulator"))]
 mod spi_adapter;
+#[cfg(not(feature="simulator"))]
 pub use spi_adapter::*;
 
+#[cfg(not(feature="simulator"))]
 pub mod bitbang_spi;
 
 pub mod io;
 
+#[cfg(not(feature="simulator"))]
 mod panic;
@@ -7,15 +7,29 @@ impl<T> SharedWithInterrupt<T> {
     pub fn new(v: T) -> Self {
         Self(UnsafeCell::new(v))
     }
+    #[cfg(not(feature="simulator"))]
     pub fn lock<R>(&self, mut f: impl FnMut(&mut T) -> R) -> R {
         let mut_self = unsafe { &mut *self.0.get() };
         cortex_m::interrupt::free(|_| f(mut_self))
     }
 
+    #[cfg(not(feature="simulator"))]
     pub unsafe fn lock_from_interrupt<R>(&self, mut f: impl FnMut(&mut T) -> R) -> R {
         let mut_self = &mut *self.0.get();
         f(mut_self)
     }
+
+    // In the simulator, interrupts are run from a different thread. Both
+    // sides must take the lock.
+    #[cfg(feature="simulator")]
+    pub fn lock<R>(&self, mut f: impl FnMut(&mut T) -> R) -> R {
+        critical_section::with(|_| f(unsafe { &mut *self.0.get() }))
+    }
+
+    #[cfg(feature="simulator")]
+    pub unsafe fn lock_from_interrupt<R>(&self, f: impl FnMut(&mut T) -> R) -> R {
+        self.lock(f)
+    }
 }
 
 unsafe impl<T> Sync for SharedWithInterrupt<T> {}
@@ -80,8 +80,10 @@ impl<T: CancellableTask + Copy + Send> TaskRunner<T> {
                 _ = self.cancel_signal.wait().fuse() => true,
             };
 
+            // The run future is dropped at this point, along with the guards
+            // it held. We are not idle until the cancellation is complete.
             if was_cancelled {
-                task.cancel(ctx);
+                task.cancel(ctx).await;
                 debug!("Task cancelled");
             } else {
                 debug!("Task complete");
@@ -103,6 +105,8 @@ pub trait CancellableTask: Send + core::fmt::Debug {
     /// The task to run
     // &mut self is not an option as we are sharing references in get_current_task()
     fn run<'a>(&'a self, ctx: &'a mut Self::Context) -> Self::RunFuture<'a>;
-    /// What to do when cancelled.
+    /// What to do when cancelled. The run future has been dropped when this
+    /// gets called. Settings that the task changed should be restored by
+    /// guards held in the run future, see MotionControlAsync::settings_guard().
     fn cancel<'a>(&'a self, ctx: &'a mut Self::Context) -> Self::CancelFuture<'a>;
 }